@@ -0,0 +1 @@
+drop table free_companies
@@ -0,0 +1,6 @@
+create table linkshells (
+  id bigint primary key,
+  data jsonb not null,
+  frecency float not null default 0.0,
+  last_update timestamp not null default now()
+)
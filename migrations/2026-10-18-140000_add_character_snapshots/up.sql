@@ -0,0 +1,13 @@
+create table character_snapshots (
+  id bigserial primary key,
+  character_id bigint not null references characters (id) on delete cascade,
+  data jsonb not null,
+  created timestamp not null default now()
+);
+
+create index character_snapshots_character_id_created_idx
+  on character_snapshots (character_id, created);
+
+-- start every existing character's history with its current data
+insert into character_snapshots (character_id, data, created)
+  select id, data, last_update from characters;
@@ -0,0 +1 @@
+drop table character_names
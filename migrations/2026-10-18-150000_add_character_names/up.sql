@@ -0,0 +1,18 @@
+create table character_names (
+  id bigserial primary key,
+  character_id bigint not null references characters (id) on delete cascade,
+  name text not null,
+  world text not null,
+  first_seen timestamp not null default now(),
+  last_seen timestamp not null default now(),
+  unique (character_id, name, world)
+);
+
+create index character_names_lower_name_world_idx
+  on character_names (lower(name), world);
+
+-- backfill from the recorded history of every character
+insert into character_names (character_id, name, world, first_seen, last_seen)
+  select character_id, data->>'name', data->>'world', min(created), max(created)
+  from character_snapshots
+  group by character_id, data->>'name', data->>'world';
@@ -0,0 +1,5 @@
+alter table characters
+  drop column name,
+  drop column world;
+
+drop index if exists characters_frecency_idx;
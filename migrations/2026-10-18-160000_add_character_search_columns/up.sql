@@ -0,0 +1,9 @@
+create extension if not exists pg_trgm;
+
+alter table characters
+  add column name text generated always as (data->>'name') stored,
+  add column world text generated always as (data->>'world') stored;
+
+create index characters_name_trgm_idx on characters using gin (name gin_trgm_ops);
+create index characters_world_idx on characters (world);
+create index characters_frecency_idx on characters (frecency desc);
@@ -0,0 +1,3 @@
+alter table characters drop column claimed_until;
+alter table free_companies drop column claimed_until;
+alter table linkshells drop column claimed_until;
@@ -0,0 +1,3 @@
+alter table characters add column claimed_until timestamp;
+alter table free_companies add column claimed_until timestamp;
+alter table linkshells add column claimed_until timestamp;
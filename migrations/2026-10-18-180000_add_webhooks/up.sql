@@ -0,0 +1,37 @@
+create table webhooks (
+  id bigserial primary key,
+  url text not null,
+  secret text not null,
+  created timestamp not null default now()
+);
+
+create table webhook_subscriptions (
+  id bigserial primary key,
+  webhook_id bigint not null references webhooks (id) on delete cascade,
+  kind text not null check (kind in ('character', 'free_company')),
+  resource_id bigint not null,
+  unique (webhook_id, kind, resource_id)
+);
+
+create index webhook_subscriptions_kind_resource_id_idx
+  on webhook_subscriptions (kind, resource_id);
+
+create table webhook_deliveries (
+  id bigserial primary key,
+  webhook_id bigint not null references webhooks (id) on delete cascade,
+  payload jsonb not null,
+  attempts integer not null default 0,
+  -- null once the delivery succeeded or ran out of attempts
+  next_attempt timestamp,
+  last_status integer,
+  last_error text,
+  delivered timestamp,
+  created timestamp not null default now()
+);
+
+create index webhook_deliveries_webhook_id_created_idx
+  on webhook_deliveries (webhook_id, created);
+
+create index webhook_deliveries_next_attempt_idx
+  on webhook_deliveries (next_attempt)
+  where next_attempt is not null;
@@ -0,0 +1,234 @@
+//! Operations on the stored data for the `lodestone_admin` binary.
+
+use crate::{
+  cache,
+  database::{
+    PostgresPool,
+    models::{
+      U64,
+      characters::{DatabaseCharacter, NewDatabaseCharacter},
+    },
+    schema::characters,
+  },
+  error::*,
+  redis::RedisPool,
+  scraper::Scraper,
+  workers::{
+    queue::{self, Priority},
+    updater,
+  },
+};
+
+use bb8_redis::redis::{self, AsyncCommands};
+
+use chrono::NaiveDateTime;
+
+use diesel::{
+  prelude::*,
+  upsert::excluded,
+};
+
+use serde_json::Value;
+
+use std::{
+  collections::HashSet,
+  fs::File,
+  io::{BufRead, BufReader, BufWriter, Write},
+};
+
+const USAGE: &str = "\
+usage: lodestone_admin <command>
+
+commands:
+  enqueue <file>        queue the character ids in a file (one per line) in the bulk lane
+  refresh <id>...       scrape stored characters again right now
+  purge <id>...         delete characters and anything cached about them
+  queue                 show the state of the character and refresh queues
+  clear-queue           empty every lane of the character and refresh queues, and their retries
+  rebase-frecency       rebase frecencies that have decayed too far (accesses aren't recorded, so
+                        frecencies can't be recomputed from scratch)
+  dump <file>           write the characters table to a file as json lines
+  load <file>           insert or replace characters from a file written by dump";
+
+/// The queues a character can be waiting in.
+const QUEUE_KINDS: &[&str] = &["character", "character_refresh"];
+
+/// A row of the characters table as written by `dump`.
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpedCharacter {
+  id: u64,
+  data: Value,
+  frecency: f64,
+  last_update: NaiveDateTime,
+}
+
+/// Runs the command given by the arguments, not including the program name.
+pub async fn run(args: &[String], db_pool: &PostgresPool, redis_pool: &RedisPool) -> Result<()> {
+  match args.first().map(String::as_str) {
+    Some("enqueue") => enqueue(db_pool, redis_pool, file_arg(args)?).await,
+    Some("refresh") => refresh(db_pool, redis_pool, &id_args(args)?).await,
+    Some("purge") => purge(db_pool, redis_pool, &id_args(args)?).await,
+    Some("queue") => show_queue(redis_pool).await,
+    Some("clear-queue") => clear_queue(redis_pool).await,
+    // only the decay is rebased. frecency is updated in place on each access and the accesses
+    // themselves aren't kept, so there is nothing to recompute it from
+    Some("rebase-frecency") => updater::prevent_underflow(&*db_pool.get()?),
+    Some("dump") => dump(db_pool, file_arg(args)?),
+    Some("load") => load(db_pool, file_arg(args)?),
+    _ => failure::bail!("{}", USAGE),
+  }
+}
+
+fn file_arg(args: &[String]) -> Result<&str> {
+  args.get(1)
+    .map(String::as_str)
+    .ok_or_else(|| failure::format_err!("{}", USAGE))
+}
+
+fn id_args(args: &[String]) -> Result<Vec<u64>> {
+  args[1..].iter().map(|id| Ok(id.parse()?)).collect()
+}
+
+async fn enqueue(db_pool: &PostgresPool, redis_pool: &RedisPool, path: &str) -> Result<()> {
+  let mut ids = Vec::new();
+  for line in BufReader::new(File::open(path)?).lines() {
+    let line = line?;
+    if !line.trim().is_empty() {
+      ids.push(line.trim().parse::<u64>()?);
+    }
+  }
+
+  // stored characters are refreshed by the updater instead
+  let stored: HashSet<u64> = characters::table
+    .filter(characters::id.eq_any(ids.iter().map(|&id| U64(id)).collect::<Vec<_>>()))
+    .select(characters::id)
+    .load::<U64>(&*db_pool.get()?)?
+    .into_iter()
+    .map(u64::from)
+    .collect();
+
+  let mut redis = redis_pool.get().await?;
+  let mut queued = 0;
+  for id in ids.into_iter().filter(|id| !stored.contains(id)) {
+    queue::enqueue(&mut *redis, "character", id, Priority::Bulk).await?;
+    queued += 1;
+  }
+  println!("queued {} characters ({} already stored)", queued, stored.len());
+  Ok(())
+}
+
+async fn refresh(db_pool: &PostgresPool, redis_pool: &RedisPool, ids: &[u64]) -> Result<()> {
+  let scraper = Scraper::new(redis_pool);
+  for &id in ids {
+    let c: Option<DatabaseCharacter> = characters::table
+      .find(U64(id))
+      .get_result(&*db_pool.get()?)
+      .optional()?;
+    match c {
+      Some(c) => match updater::update_character(db_pool, &c, &scraper).await {
+        Ok(()) => println!("refreshed {}", id),
+        Err(e) => eprintln!("could not refresh {}: {}", id, e),
+      },
+      None => eprintln!("{} is not stored", id),
+    }
+  }
+  Ok(())
+}
+
+async fn purge(db_pool: &PostgresPool, redis_pool: &RedisPool, ids: &[u64]) -> Result<()> {
+  let deleted = diesel::delete(characters::table)
+    .filter(characters::id.eq_any(ids.iter().map(|&id| U64(id)).collect::<Vec<_>>()))
+    .execute(&*db_pool.get()?)?;
+
+  let mut redis = redis_pool.get().await?;
+  for &id in ids {
+    // take the character out of every queue too, or the queue worker would scrape it back
+    for kind in QUEUE_KINDS {
+      queue::remove(&mut *redis, kind, id).await?;
+    }
+    redis::pipe()
+      .del(format!("character_{}", id)).ignore()
+      .del(format!("character_refresh_cooldown_{}", id)).ignore()
+      .hdel(queue::DEAD_LETTERS, format!("character_{}", id)).ignore()
+      .query_async::<_, ()>(&mut *redis)
+      .await?;
+    // the web processes may hold a negative result for the character in memory
+    cache::local::invalidate(&mut *redis, &[format!("character_{}", id)]).await?;
+  }
+  println!("deleted {} characters", deleted);
+  Ok(())
+}
+
+async fn show_queue(redis_pool: &RedisPool) -> Result<()> {
+  let mut redis = redis_pool.get().await?;
+  let retries: Vec<String> = redis.zrange(queue::RETRIES, 0, -1).await?;
+  for kind in QUEUE_KINDS {
+    for &priority in &Priority::ALL {
+      let lane = priority.queue(kind);
+      let len: u64 = redis.llen(&lane).await?;
+      let retrying = retries.iter()
+        .filter(|entry| entry.rfind(':').map(|i| &entry[..i]) == Some(lane.as_str()))
+        .count();
+      println!("{}: {} waiting, {} waiting to retry", lane, len, retrying);
+    }
+    let hashed: u64 = redis.hlen(format!("{}_queue_hash", kind)).await?;
+    println!("{}_queue_hash: {} entries", kind, hashed);
+  }
+  Ok(())
+}
+
+async fn clear_queue(redis_pool: &RedisPool) -> Result<()> {
+  let mut redis = redis_pool.get().await?;
+  for kind in QUEUE_KINDS {
+    queue::clear(&mut *redis, kind).await?;
+  }
+  println!("cleared the character queues");
+  Ok(())
+}
+
+fn dump(db_pool: &PostgresPool, path: &str) -> Result<()> {
+  let chars: Vec<DatabaseCharacter> = characters::table
+    .order(characters::id.asc())
+    .load(&*db_pool.get()?)?;
+  let mut out = BufWriter::new(File::create(path)?);
+  for c in &chars {
+    let dumped = DumpedCharacter {
+      id: *c.id,
+      data: c.data.clone(),
+      frecency: c.frecency,
+      last_update: c.last_update,
+    };
+    serde_json::to_writer(&mut out, &dumped)?;
+    out.write_all(b"\n")?;
+  }
+  out.flush()?;
+  println!("dumped {} characters", chars.len());
+  Ok(())
+}
+
+fn load(db_pool: &PostgresPool, path: &str) -> Result<()> {
+  let conn = db_pool.get()?;
+  let mut loaded = 0;
+  for line in BufReader::new(File::open(path)?).lines() {
+    let dumped: DumpedCharacter = serde_json::from_str(&line?)?;
+    let ndc = NewDatabaseCharacter {
+      id: dumped.id.into(),
+      data: dumped.data,
+      frecency: dumped.frecency,
+      last_update: dumped.last_update,
+    };
+    diesel::insert_into(characters::table)
+      .values(&ndc)
+      .on_conflict(characters::id)
+      .do_update()
+      .set((
+        characters::data.eq(excluded(characters::data)),
+        characters::frecency.eq(excluded(characters::frecency)),
+        characters::last_update.eq(excluded(characters::last_update)),
+      ))
+      .execute(&*conn)?;
+    loaded += 1;
+  }
+  println!("loaded {} characters", loaded);
+  Ok(())
+}
@@ -0,0 +1,20 @@
+//! Command-line tools for operating on the stored characters and the scrape queue.
+//!
+//! Run without arguments for usage.
+
+fn main() {
+  let runtime = tokio::runtime::Builder::new()
+    .threaded_scheduler()
+    .enable_all()
+    .build()
+    .expect("could not create tokio runtime");
+
+  let db_pool = lodestone_api::database::pool();
+  let redis_pool = runtime.handle().block_on(lodestone_api::redis::pool());
+
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if let Err(e) = runtime.handle().block_on(lodestone_api::admin::run(&args, &db_pool, &redis_pool)) {
+    eprintln!("{}", e);
+    std::process::exit(1);
+  }
+}
@@ -0,0 +1,26 @@
+//! Runs the scrape queue, updater and webhook deliverer without the web server.
+//!
+//! Which workers run is chosen with the `RUN_QUEUE`, `RUN_UPDATER` and `RUN_WEBHOOKS` env vars, and
+//! the number of queue consumers with `QUEUE_WORKERS`.
+
+use lodestone_api::scraper::Scraper;
+
+fn main() {
+  let runtime = tokio::runtime::Builder::new()
+    .threaded_scheduler()
+    .enable_all()
+    .build()
+    .expect("could not create tokio runtime");
+
+  let db_pool = lodestone_api::database::pool();
+  let redis_pool = runtime.handle().block_on(lodestone_api::redis::pool());
+
+  let scraper = Scraper::new(&redis_pool);
+
+  runtime.enter(|| lodestone_api::workers::start(&redis_pool, &db_pool, &scraper));
+
+  // the workers run on the runtime's threads forever
+  loop {
+    std::thread::park();
+  }
+}
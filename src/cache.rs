@@ -0,0 +1,341 @@
+//! The Redis cache of resources scraped on request, used by the `cached!` macro.
+
+use crate::{
+  config::CONFIG,
+  error::*,
+  redis::RedisPool,
+  routes::RouteResult,
+};
+
+use bb8_redis::redis::{self, AsyncCommands};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use sha2::{Digest, Sha256};
+
+use std::{
+  collections::BTreeMap,
+  fmt::Display,
+  future::Future,
+  str::FromStr,
+};
+
+pub mod local;
+
+/// The version of the format of cached resources, which is part of every key. Bump it when the
+/// parser's models change so that entries in the old format are no longer read.
+crate const SCHEMA_VERSION: u32 = 1;
+
+/// How many seconds a refresh of a stale resource may take before another request can start one.
+const REFRESH_LOCK_TTL: usize = 60;
+
+/// The kinds of resources that are cached, each with its own TTLs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+crate enum CacheKind {
+  CharacterSearch,
+  FreeCompanySearch,
+  Linkshell,
+  LinkshellSearch,
+}
+
+impl CacheKind {
+  crate const ALL: [CacheKind; 4] = [
+    CacheKind::CharacterSearch,
+    CacheKind::FreeCompanySearch,
+    CacheKind::Linkshell,
+    CacheKind::LinkshellSearch,
+  ];
+
+  crate fn from_name(name: &str) -> Option<CacheKind> {
+    CacheKind::ALL.iter().cloned().find(|kind| kind.name() == name)
+  }
+
+  crate fn name(self) -> &'static str {
+    match self {
+      CacheKind::CharacterSearch => "character_search",
+      CacheKind::FreeCompanySearch => "free_company_search",
+      CacheKind::Linkshell => "linkshell",
+      CacheKind::LinkshellSearch => "linkshell_search",
+    }
+  }
+
+  crate fn ttl(self) -> CacheTtl {
+    match self {
+      CacheKind::CharacterSearch => CONFIG.character_search_ttl,
+      CacheKind::FreeCompanySearch => CONFIG.free_company_search_ttl,
+      CacheKind::Linkshell => CONFIG.linkshell_ttl,
+      CacheKind::LinkshellSearch => CONFIG.linkshell_search_ttl,
+    }
+  }
+}
+
+/// How long a kind of resource is cached.
+#[derive(Debug, Clone, Copy)]
+crate struct CacheTtl {
+  /// Seconds before the resource is refreshed, after which it is served stale
+  crate soft: usize,
+  /// Seconds before the resource is removed, after which requests wait for a new scrape
+  crate hard: usize,
+}
+
+/// Builds the key of a cached resource from the parameters of the request for it, like
+/// `cache:v1:linkshell:<id>:<hash>`.
+///
+/// The hash is a SHA-256 of the parameters sorted by name. Parameters should be normalised with
+/// `name_param` and `parsed_param` so that requests the Lodestone treats the same share a key.
+crate struct CacheKey {
+  kind: CacheKind,
+  id: Option<u64>,
+  params: BTreeMap<&'static str, String>,
+}
+
+impl CacheKey {
+  crate fn new(kind: CacheKind) -> Self {
+    CacheKey {
+      kind,
+      id: None,
+      params: BTreeMap::new(),
+    }
+  }
+
+  /// Sets the ID of the resource, which is kept readable in the key.
+  crate fn id(mut self, id: u64) -> Self {
+    self.id = Some(id);
+    self
+  }
+
+  /// Adds a parameter if it is set.
+  crate fn param<V: Display>(mut self, name: &'static str, value: Option<V>) -> Self {
+    if let Some(value) = value {
+      self.params.insert(name, value.to_string());
+    }
+    self
+  }
+
+  crate fn build(self) -> String {
+    let params = serde_json::to_string(&self.params).expect("string maps always serialise");
+    let hash: String = Sha256::digest(params.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    match self.id {
+      Some(id) => format!("{}{}:{}", prefix(SCHEMA_VERSION, self.kind), id, hash),
+      None => format!("{}{}", prefix(SCHEMA_VERSION, self.kind), hash),
+    }
+  }
+}
+
+/// The start of the keys of every cached resource of a kind in a schema version.
+crate fn prefix(version: u32, kind: CacheKind) -> String {
+  format!("cache:v{}:{}:", version, kind.name())
+}
+
+/// The pattern matching the keys of cached resources in a schema version, of one kind or all kinds,
+/// and with one ID or any ID.
+crate fn pattern(version: u32, kind: Option<CacheKind>, id: Option<u64>) -> String {
+  match (kind, id) {
+    (Some(kind), Some(id)) => format!("{}{}:*", prefix(version, kind), id),
+    (Some(kind), None) => format!("{}*", prefix(version, kind)),
+    (None, _) => format!("cache:v{}:*", version),
+  }
+}
+
+/// The kinds of resources that the queue worker caches negative results for, as `<kind>_<id>` for
+/// `CACHE_TTL_NOT_FOUND` seconds. These keys aren't versioned.
+crate const NOT_FOUND_KINDS: &[&str] = &["character", "free_company", "linkshell"];
+
+/// The pattern matching the cached negative results of a kind, with one ID or any ID.
+crate fn not_found_pattern(kind: &str, id: Option<u64>) -> String {
+  match id {
+    Some(id) => format!("{}_{}", kind, id),
+    None => format!("{}_[0-9]*", kind),
+  }
+}
+
+/// Whether a key holds a cached resource or negative result, rather than the queues and other state
+/// that share Redis with the cache.
+crate fn is_cached(key: &str) -> bool {
+  if key.starts_with("cache:") {
+    return true;
+  }
+  NOT_FOUND_KINDS.iter().any(|kind| {
+    key.strip_prefix(kind)
+      .and_then(|rest| rest.strip_prefix('_'))
+      .map_or(false, |id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+  })
+}
+
+/// Normalises a name by trimming it, collapsing runs of whitespace and folding its case. Empty
+/// names are treated as missing.
+crate fn name_param(name: &Option<String>) -> Option<String> {
+  let name = name.as_ref()?.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+  if name.is_empty() {
+    None
+  } else {
+    Some(name)
+  }
+}
+
+/// A parsed search parameter with a name to use in cache keys.
+///
+/// The names are spelled out rather than taken from `Debug`, so keys only change when we change
+/// them.
+crate trait ParamName {
+  fn param_name(&self) -> &'static str;
+}
+
+/// Normalises a parameter by parsing it, treating parameters that don't parse as missing like the
+/// search routes do.
+crate fn parsed_param<T>(value: &Option<String>) -> Option<&'static str>
+  where T: FromStr + ParamName,
+{
+  value.as_ref()
+    .and_then(|v| T::from_str(v).ok())
+    .map(|v| v.param_name())
+}
+
+/// A resource in the cache, which is kept until its hard expiry.
+#[derive(Debug, Serialize, Deserialize)]
+crate struct CacheEntry<T> {
+  crate result: T,
+  crate cached_at: DateTime<Utc>,
+  /// When the resource should be refreshed
+  crate soft_expires: DateTime<Utc>,
+}
+
+impl<T> CacheEntry<T> {
+  crate fn into_result(self, stale: bool) -> RouteResult<T> {
+    RouteResult::Cached {
+      result: self.result,
+      cached_at: self.cached_at,
+      expires: self.soft_expires,
+      stale,
+    }
+  }
+}
+
+/// Finds a cached resource. Entries that can't be read are treated as missing.
+crate async fn find<T>(pool: &RedisPool, key: &str) -> Result<Option<CacheEntry<T>>>
+  where T: DeserializeOwned,
+{
+  if let Some((json, _)) = local::get(key) {
+    if let Ok(entry) = serde_json::from_str(&json) {
+      return Ok(Some(entry));
+    }
+  }
+  let mut redis = pool.get().await?;
+  if !local::enabled() {
+    let json: Option<String> = redis.get(key).await?;
+    return Ok(json.and_then(|x| serde_json::from_str(&x).ok()));
+  }
+  let (json, expires_in): (Option<String>, i64) = redis::pipe()
+    .get(key)
+    .cmd("PTTL").arg(key)
+    .query_async(&mut *redis)
+    .await?;
+  let json = match json {
+    Some(j) => j,
+    None => return Ok(None),
+  };
+  let entry: CacheEntry<T> = match serde_json::from_str(&json) {
+    Ok(e) => e,
+    Err(_) => return Ok(None),
+  };
+  let expires = Utc::now() + Duration::milliseconds(expires_in);
+  local::insert(key, &json, expires.min(entry.soft_expires));
+  Ok(Some(entry))
+}
+
+/// Caches a resource until the hard expiry of its kind.
+crate async fn put<T>(pool: &RedisPool, kind: CacheKind, key: &str, val: T) -> Result<CacheEntry<T>>
+  where T: Serialize,
+{
+  let ttl = kind.ttl();
+  let mut redis = pool.get().await?;
+  // we only want second resolution
+  let cached_at = Utc.timestamp(Utc::now().timestamp(), 0);
+  let entry = CacheEntry {
+    result: val,
+    cached_at,
+    soft_expires: cached_at + Duration::seconds(ttl.soft as i64),
+  };
+  let json = serde_json::to_string(&entry)?;
+  redis.set_ex(key, &json, ttl.hard).await?;
+  // the soft expiry is never after the hard one
+  local::insert(key, &json, entry.soft_expires);
+  Ok(entry)
+}
+
+/// Tries to take the lock on refreshing a cached resource, returning whether it was taken.
+crate async fn lock_refresh(pool: &RedisPool, key: &str) -> Result<bool> {
+  let mut redis = pool.get().await?;
+  let set: Option<String> = redis::cmd("SET")
+    .arg(lock_key(key))
+    .arg(1)
+    .arg("NX")
+    .arg("EX")
+    .arg(REFRESH_LOCK_TTL)
+    .query_async(&mut *redis)
+    .await?;
+  Ok(set.is_some())
+}
+
+/// Refreshes a cached resource with the result of a future, then releases the refresh lock.
+///
+/// If the scrape fails, the lock is left to expire so that requests for the stale resource don't
+/// each start another scrape in the meantime.
+crate async fn refresh<T, F>(pool: &RedisPool, kind: CacheKind, key: &str, fut: F) -> Result<()>
+  where T: Serialize,
+        F: Future<Output = RouteResult<T>>,
+{
+  let result = match fut.await {
+    RouteResult::Scraped { result } => result,
+    _ => return Ok(()),
+  };
+  put(pool, kind, key, result).await?;
+  let mut redis = pool.get().await?;
+  redis.del(lock_key(key)).await?;
+  Ok(())
+}
+
+/// The key of the lock on refreshing a cached resource, which is kept out of the `cache:` namespace.
+fn lock_key(key: &str) -> String {
+  format!("refreshing:{}", key)
+}
+
+/// Finds up to `limit` keys matching a pattern, without blocking Redis like `KEYS` would. Only keys
+/// of cached resources and negative results are returned, whatever the pattern.
+crate async fn keys(pool: &RedisPool, pattern: &str, limit: usize) -> Result<Vec<String>> {
+  let mut redis = pool.get().await?;
+  let mut keys = Vec::new();
+  let mut cursor = 0;
+  loop {
+    let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+      .arg(cursor)
+      .arg("MATCH")
+      .arg(pattern)
+      .arg("COUNT")
+      .arg(1000)
+      .query_async(&mut *redis)
+      .await?;
+    keys.extend(batch.into_iter().filter(|key| is_cached(key)));
+    if next == 0 || keys.len() >= limit {
+      break;
+    }
+    cursor = next;
+  }
+  keys.truncate(limit);
+  Ok(keys)
+}
+
+/// Removes every cached resource with a key matching a pattern, returning how many were removed.
+crate async fn purge(pool: &RedisPool, pattern: &str) -> Result<u64> {
+  let keys = keys(pool, pattern, usize::max_value()).await?;
+  let mut redis = pool.get().await?;
+  let mut removed = 0;
+  for chunk in keys.chunks(1000) {
+    let n: u64 = redis.del(chunk).await?;
+    removed += n;
+    local::invalidate(&mut *redis, chunk).await?;
+  }
+  Ok(removed)
+}
@@ -0,0 +1,283 @@
+//! An optional tier of the cache kept in memory, consulted before Redis so that hot resources are
+//! served without a round trip.
+//!
+//! Entries are kept no longer than Redis keeps them, and no longer than their soft expiry, so that
+//! stale resources are always looked up in Redis where other processes may have refreshed them.
+//!
+//! Keys removed from the cache by admins are published on `INVALIDATIONS` so every process removes
+//! them from its local cache. In case an invalidation is missed, entries are also kept no longer
+//! than `LOCAL_CACHE_MAX_AGE`.
+
+use crate::{
+  config::CONFIG,
+  error::*,
+};
+
+use bb8_redis::redis::{self, aio::Connection};
+
+use chrono::{DateTime, Duration, Utc};
+
+use lazy_static::lazy_static;
+
+use tokio::stream::StreamExt;
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::Mutex,
+};
+
+/// The Redis pub/sub channel that keys removed from the cache are published to.
+const INVALIDATIONS: &str = "cache_invalidations";
+
+lazy_static! {
+  /// The local cache, which is disabled unless `LOCAL_CACHE_ENTRIES` is set.
+  static ref LOCAL: Option<Mutex<LocalCache>> = match CONFIG.local_cache_entries {
+    0 => None,
+    entries => Some(Mutex::new(LocalCache::new(entries, CONFIG.local_cache_bytes))),
+  };
+}
+
+/// Whether the local cache is enabled.
+crate fn enabled() -> bool {
+  LOCAL.is_some()
+}
+
+/// Finds the JSON cached under a key and when it expires.
+crate fn get(key: &str) -> Option<(String, DateTime<Utc>)> {
+  LOCAL.as_ref()?.lock().ok()?.get(key, Utc::now())
+}
+
+/// Caches JSON under a key until it expires or `LOCAL_CACHE_MAX_AGE` passes, evicting the least
+/// recently used entries to make room.
+crate fn insert(key: &str, json: &str, expires: DateTime<Utc>) {
+  if let Some(Ok(mut local)) = LOCAL.as_ref().map(|l| l.lock()) {
+    let now = Utc::now();
+    let expires = expires.min(now + Duration::seconds(CONFIG.local_cache_max_age as i64));
+    local.insert(key, json, expires, now);
+  }
+}
+
+fn remove(key: &str) {
+  if let Some(Ok(mut local)) = LOCAL.as_ref().map(|l| l.lock()) {
+    local.remove(key);
+  }
+}
+
+fn clear() {
+  if let Some(Ok(mut local)) = LOCAL.as_ref().map(|l| l.lock()) {
+    let (max_entries, max_bytes) = (local.max_entries, local.max_bytes);
+    *local = LocalCache::new(max_entries, max_bytes);
+  }
+}
+
+/// Removes keys from the local cache of this process and publishes them so every other process
+/// removes them too.
+crate async fn invalidate(redis: &mut Connection, keys: &[String]) -> Result<()> {
+  if keys.is_empty() {
+    return Ok(());
+  }
+  let mut pipe = redis::pipe();
+  for key in keys {
+    remove(key);
+    pipe.publish(INVALIDATIONS, key).ignore();
+  }
+  pipe.query_async::<_, ()>(redis).await?;
+  Ok(())
+}
+
+/// Spawns a task removing the keys other processes invalidate from the local cache, if it is
+/// enabled.
+///
+/// Invalidations published while the task isn't subscribed are missed, so the local cache is
+/// cleared whenever it subscribes.
+pub fn listen() {
+  if !enabled() {
+    return;
+  }
+  tokio::task::spawn(async {
+    async fn inner() -> Result<()> {
+      let mut pubsub = crate::redis::pubsub().await?;
+      pubsub.subscribe(INVALIDATIONS).await?;
+      clear();
+      let mut messages = pubsub.on_message();
+      while let Some(msg) = messages.next().await {
+        let key: String = msg.get_payload()?;
+        remove(&key);
+      }
+      failure::bail!("subscription to {} ended", INVALIDATIONS)
+    }
+    loop {
+      if let Err(e) = inner().await {
+        eprintln!("error in cache invalidation task: {}", e);
+      }
+      // forget everything while the invalidations can't be heard
+      clear();
+      tokio::time::delay_for(Duration::seconds(5).to_std().unwrap()).await;
+    }
+  });
+}
+
+struct Entry {
+  json: String,
+  expires: DateTime<Utc>,
+  /// When the entry was last used, as a key of `LocalCache::recency`
+  used: u64,
+}
+
+/// A least-recently-used cache of JSON, bounded by its number of entries and the bytes of their keys
+/// and JSON.
+struct LocalCache {
+  max_entries: usize,
+  max_bytes: usize,
+  bytes: usize,
+  /// Counts uses of entries, ordering them by recency
+  clock: u64,
+  entries: HashMap<String, Entry>,
+  /// Keys by when they were last used
+  recency: BTreeMap<u64, String>,
+}
+
+impl LocalCache {
+  fn new(max_entries: usize, max_bytes: usize) -> Self {
+    LocalCache {
+      max_entries,
+      max_bytes,
+      bytes: 0,
+      clock: 0,
+      entries: HashMap::new(),
+      recency: BTreeMap::new(),
+    }
+  }
+
+  fn get(&mut self, key: &str, now: DateTime<Utc>) -> Option<(String, DateTime<Utc>)> {
+    let expired = self.entries.get(key)?.expires <= now;
+    if expired {
+      self.remove(key);
+      return None;
+    }
+    self.clock += 1;
+    let entry = self.entries.get_mut(key)?;
+    self.recency.remove(&entry.used);
+    entry.used = self.clock;
+    self.recency.insert(self.clock, key.to_string());
+    Some((entry.json.clone(), entry.expires))
+  }
+
+  fn insert(&mut self, key: &str, json: &str, expires: DateTime<Utc>, now: DateTime<Utc>) {
+    self.remove(key);
+    let size = key.len() + json.len();
+    if size > self.max_bytes || expires <= now {
+      return;
+    }
+    while self.entries.len() >= self.max_entries || self.bytes + size > self.max_bytes {
+      if !self.evict() {
+        break;
+      }
+    }
+    self.clock += 1;
+    self.bytes += size;
+    self.recency.insert(self.clock, key.to_string());
+    self.entries.insert(key.to_string(), Entry {
+      json: json.to_string(),
+      expires,
+      used: self.clock,
+    });
+  }
+
+  fn remove(&mut self, key: &str) {
+    if let Some(entry) = self.entries.remove(key) {
+      self.recency.remove(&entry.used);
+      self.bytes -= key.len() + entry.json.len();
+    }
+  }
+
+  /// Removes the least recently used entry, returning whether there was one.
+  fn evict(&mut self) -> bool {
+    let key = match self.recency.values().next() {
+      Some(k) => k.clone(),
+      None => return false,
+    };
+    self.remove(&key);
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cache(max_entries: usize, max_bytes: usize) -> (LocalCache, DateTime<Utc>, DateTime<Utc>) {
+    let now = Utc::now();
+    (LocalCache::new(max_entries, max_bytes), now, now + Duration::minutes(1))
+  }
+
+  #[test]
+  fn entries_are_found_until_they_expire() {
+    let (mut local, now, expires) = cache(10, 1024);
+    local.insert("a", "1", expires, now);
+    assert_eq!(local.get("a", now), Some(("1".to_string(), expires)));
+    assert_eq!(local.get("a", expires), None);
+    assert_eq!(local.entries.len(), 0);
+    assert_eq!(local.bytes, 0);
+  }
+
+  #[test]
+  fn expired_entries_are_not_inserted() {
+    let (mut local, now, _) = cache(10, 1024);
+    local.insert("a", "1", now, now);
+    assert_eq!(local.get("a", now), None);
+    assert_eq!(local.bytes, 0);
+  }
+
+  #[test]
+  fn least_recently_used_entries_are_evicted_past_the_entry_limit() {
+    let (mut local, now, expires) = cache(2, 1024);
+    local.insert("a", "1", expires, now);
+    local.insert("b", "2", expires, now);
+    // using a makes b the least recently used
+    assert!(local.get("a", now).is_some());
+    local.insert("c", "3", expires, now);
+    assert!(local.get("a", now).is_some());
+    assert_eq!(local.get("b", now), None);
+    assert!(local.get("c", now).is_some());
+    assert_eq!(local.entries.len(), 2);
+    assert_eq!(local.recency.len(), 2);
+  }
+
+  #[test]
+  fn least_recently_used_entries_are_evicted_past_the_byte_limit() {
+    let (mut local, now, expires) = cache(10, 10);
+    local.insert("a", "1234", expires, now);
+    local.insert("b", "1234", expires, now);
+    assert_eq!(local.bytes, 10);
+    local.insert("c", "1", expires, now);
+    assert_eq!(local.get("a", now), None);
+    assert!(local.get("b", now).is_some());
+    assert!(local.get("c", now).is_some());
+    assert_eq!(local.bytes, 7);
+  }
+
+  #[test]
+  fn entries_larger_than_the_cache_are_not_inserted() {
+    let (mut local, now, expires) = cache(10, 10);
+    local.insert("a", "1", expires, now);
+    local.insert("b", "1234567890", expires, now);
+    assert_eq!(local.get("b", now), None);
+    assert!(local.get("a", now).is_some());
+    assert_eq!(local.bytes, 2);
+  }
+
+  #[test]
+  fn bytes_are_counted_when_entries_are_replaced_and_removed() {
+    let (mut local, now, expires) = cache(10, 1024);
+    local.insert("a", "1", expires, now);
+    local.insert("a", "12345", expires, now);
+    assert_eq!(local.bytes, 6);
+    assert_eq!(local.get("a", now), Some(("12345".to_string(), expires)));
+    assert_eq!(local.recency.len(), 1);
+    local.remove("a");
+    assert_eq!(local.bytes, 0);
+    assert!(local.entries.is_empty());
+    assert!(local.recency.is_empty());
+  }
+}
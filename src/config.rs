@@ -0,0 +1,127 @@
+use crate::cache::CacheTtl;
+
+use lazy_static::lazy_static;
+
+use std::{env, fmt::Debug, str::FromStr};
+
+lazy_static! {
+  /// Settings read from environment variables.
+  crate static ref CONFIG: Config = Config::from_env();
+}
+
+crate struct Config {
+  /// How many times a resource is scraped before it is moved to the dead-letter queue
+  /// (`MAX_SCRAPE_ATTEMPTS`)
+  crate max_scrape_attempts: u32,
+  /// Seconds before the first retry of a failed scrape, doubled for each retry after it
+  /// (`SCRAPE_RETRY_DELAY`)
+  crate scrape_retry_delay: u64,
+  /// If `workers::start` runs the queue worker (`RUN_QUEUE`)
+  crate run_queue: bool,
+  /// If `workers::start` runs the updater (`RUN_UPDATER`)
+  crate run_updater: bool,
+  /// If `workers::start` runs the webhook deliverer (`RUN_WEBHOOKS`)
+  crate run_webhooks: bool,
+  /// How many resources the queue worker scrapes at once (`QUEUE_WORKERS`)
+  crate queue_workers: usize,
+  /// How many requests per second may be made to the Lodestone by every process together
+  /// (`LODESTONE_RATE`)
+  crate lodestone_rate: f64,
+  /// How many requests may be made to the Lodestone at once after a quiet period
+  /// (`LODESTONE_BURST`)
+  crate lodestone_burst: f64,
+  /// The bearer token required by admin routes, which are disabled if it is unset (`ADMIN_TOKEN`)
+  crate admin_token: Option<String>,
+  /// Seconds a client must wait between refreshes of the same character (`REFRESH_COOLDOWN`)
+  crate refresh_cooldown: usize,
+  /// The most seconds a lookup may wait for a queued resource to be scraped (`MAX_WAIT`)
+  crate max_wait: u64,
+  /// How many lookups may wait for queued resources at once, which should be kept below the number
+  /// of Rocket workers since each waiting lookup holds one (`MAX_WAITERS`)
+  crate max_waiters: usize,
+  /// How many character event streams may be open at once, which should be kept below the number of
+  /// Rocket workers since each stream holds one (`MAX_STREAMS`)
+  crate max_streams: usize,
+  /// The most seconds a character event stream stays open (`MAX_STREAM_SECS`)
+  crate max_stream_secs: u64,
+  /// How many times a webhook delivery is attempted before giving up (`WEBHOOK_ATTEMPTS`)
+  crate webhook_attempts: u32,
+  /// Seconds before the first retry of a failed webhook delivery, doubled for each retry after it
+  /// (`WEBHOOK_RETRY_DELAY`)
+  crate webhook_retry_delay: u64,
+  /// How long character searches are cached (`CACHE_TTL_CHARACTER_SEARCH` and
+  /// `CACHE_HARD_TTL_CHARACTER_SEARCH`)
+  crate character_search_ttl: CacheTtl,
+  /// How long free company searches are cached (`CACHE_TTL_FREE_COMPANY_SEARCH` and
+  /// `CACHE_HARD_TTL_FREE_COMPANY_SEARCH`)
+  crate free_company_search_ttl: CacheTtl,
+  /// How long pages of linkshells are cached (`CACHE_TTL_LINKSHELL` and `CACHE_HARD_TTL_LINKSHELL`)
+  crate linkshell_ttl: CacheTtl,
+  /// How long linkshell searches are cached (`CACHE_TTL_LINKSHELL_SEARCH` and
+  /// `CACHE_HARD_TTL_LINKSHELL_SEARCH`)
+  crate linkshell_search_ttl: CacheTtl,
+  /// Seconds that resources the queue worker found not to exist are remembered
+  /// (`CACHE_TTL_NOT_FOUND`)
+  crate not_found_ttl: usize,
+  /// How many resources are cached in memory in front of Redis, which is disabled if zero
+  /// (`LOCAL_CACHE_ENTRIES`)
+  crate local_cache_entries: usize,
+  /// How many bytes of resources are cached in memory in front of Redis (`LOCAL_CACHE_BYTES`)
+  crate local_cache_bytes: usize,
+  /// The most seconds a resource is cached in memory, in case the process misses its invalidation
+  /// (`LOCAL_CACHE_MAX_AGE`)
+  crate local_cache_max_age: u64,
+}
+
+impl Config {
+  fn from_env() -> Self {
+    Config {
+      max_scrape_attempts: var("MAX_SCRAPE_ATTEMPTS", 5),
+      scrape_retry_delay: var("SCRAPE_RETRY_DELAY", 30),
+      run_queue: var("RUN_QUEUE", true),
+      run_updater: var("RUN_UPDATER", true),
+      run_webhooks: var("RUN_WEBHOOKS", true),
+      queue_workers: var("QUEUE_WORKERS", 1),
+      lodestone_rate: var("LODESTONE_RATE", 1.0),
+      lodestone_burst: var("LODESTONE_BURST", 5.0),
+      admin_token: env::var("ADMIN_TOKEN").ok(),
+      refresh_cooldown: var("REFRESH_COOLDOWN", 600),
+      max_wait: var("MAX_WAIT", 10),
+      max_waiters: var("MAX_WAITERS", 4),
+      max_streams: var("MAX_STREAMS", 4),
+      max_stream_secs: var("MAX_STREAM_SECS", 300),
+      webhook_attempts: var("WEBHOOK_ATTEMPTS", 8),
+      webhook_retry_delay: var("WEBHOOK_RETRY_DELAY", 60),
+      character_search_ttl: cache_ttl("CHARACTER_SEARCH"),
+      free_company_search_ttl: cache_ttl("FREE_COMPANY_SEARCH"),
+      linkshell_ttl: cache_ttl("LINKSHELL"),
+      linkshell_search_ttl: cache_ttl("LINKSHELL_SEARCH"),
+      not_found_ttl: var("CACHE_TTL_NOT_FOUND", 1800),
+      local_cache_entries: var("LOCAL_CACHE_ENTRIES", 0),
+      local_cache_bytes: var("LOCAL_CACHE_BYTES", 64 * 1024 * 1024),
+      local_cache_max_age: var("LOCAL_CACHE_MAX_AGE", 60),
+    }
+  }
+}
+
+/// Parses an environment variable, using the default if it is unset.
+fn var<T>(name: &str, default: T) -> T
+  where T: FromStr,
+        T::Err: Debug,
+{
+  match env::var(name) {
+    Ok(v) => v.parse().unwrap_or_else(|e| panic!("invalid {} env var: {:?}", name, e)),
+    Err(_) => default,
+  }
+}
+
+/// Reads the soft and hard TTLs of a kind of cached resource, which default to an hour and a day.
+fn cache_ttl(kind: &str) -> CacheTtl {
+  let soft = var(&format!("CACHE_TTL_{}", kind), 3600);
+  let hard = var(&format!("CACHE_HARD_TTL_{}", kind), 86400);
+  CacheTtl {
+    soft,
+    // resources can't be served stale after they're gone
+    hard: hard.max(soft),
+  }
+}
@@ -16,7 +16,7 @@ use std::{env, ops::Deref};
 crate mod models;
 crate mod schema;
 
-crate type PostgresPool = Pool<ConnectionManager<PgConnection>>;
+pub type PostgresPool = Pool<ConnectionManager<PgConnection>>;
 
 pub fn pool() -> PostgresPool {
   let database_url = env::var("DATABASE_URL").expect("missing DATABASE_URL env var");
@@ -1,4 +1,9 @@
+pub mod character_names;
+pub mod character_snapshots;
 pub mod characters;
+pub mod free_companies;
+pub mod linkshells;
+pub mod webhooks;
 
 use std::ops::Deref;
 use std::error::Error;
@@ -0,0 +1,59 @@
+use crate::database::{
+  models::U64,
+  schema::character_names,
+};
+
+use chrono::NaiveDateTime;
+
+use diesel::{
+  pg::PgConnection,
+  prelude::*,
+};
+
+use serde_json::Value;
+
+#[derive(Debug, Queryable, Identifiable)]
+#[table_name = "character_names"]
+crate struct CharacterName {
+  crate id: i64,
+  crate character_id: U64,
+  crate name: String,
+  crate world: String,
+  crate first_seen: NaiveDateTime,
+  crate last_seen: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "character_names"]
+crate struct NewCharacterName<'a> {
+  crate character_id: U64,
+  crate name: &'a str,
+  crate world: &'a str,
+  crate first_seen: NaiveDateTime,
+  crate last_seen: NaiveDateTime,
+}
+
+impl NewCharacterName<'a> {
+  /// Records that a serialised character was seen with its current name and world at the given
+  /// time.
+  crate fn record(conn: &PgConnection, id: U64, data: &'a Value, seen: NaiveDateTime) -> QueryResult<()> {
+    let (name, world) = match (data["name"].as_str(), data["world"].as_str()) {
+      (Some(n), Some(w)) => (n, w),
+      _ => return Ok(()),
+    };
+    let new = NewCharacterName {
+      character_id: id,
+      name,
+      world,
+      first_seen: seen,
+      last_seen: seen,
+    };
+    diesel::insert_into(character_names::table)
+      .values(&new)
+      .on_conflict((character_names::character_id, character_names::name, character_names::world))
+      .do_update()
+      .set(character_names::last_seen.eq(seen))
+      .execute(conn)?;
+    Ok(())
+  }
+}
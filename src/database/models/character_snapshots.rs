@@ -0,0 +1,25 @@
+use crate::database::{
+  models::U64,
+  schema::character_snapshots,
+};
+
+use chrono::NaiveDateTime;
+
+use serde_json::Value;
+
+#[derive(Debug, Queryable, Identifiable)]
+#[table_name = "character_snapshots"]
+crate struct CharacterSnapshot {
+  crate id: i64,
+  crate character_id: U64,
+  crate data: Value,
+  crate created: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "character_snapshots"]
+crate struct NewCharacterSnapshot {
+  crate character_id: U64,
+  crate data: Value,
+  crate created: NaiveDateTime,
+}
@@ -7,13 +7,19 @@ use chrono::NaiveDateTime;
 
 use serde_json::Value;
 
-#[derive(Debug, Queryable, Identifiable, AsChangeset)]
+#[derive(Debug, Queryable, Identifiable)]
 #[table_name = "characters"]
 crate struct DatabaseCharacter {
   crate id: U64,
   crate data: Value,
   crate frecency: f64,
   crate last_update: NaiveDateTime,
+  /// The character's name, generated from `data`
+  crate name: Option<String>,
+  /// The character's world, generated from `data`
+  crate world: Option<String>,
+  /// When the updater that claimed this for refreshing gives up its claim
+  crate claimed_until: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Insertable)]
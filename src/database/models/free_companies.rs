@@ -0,0 +1,28 @@
+use crate::database::{
+  models::U64,
+  schema::free_companies,
+};
+
+use chrono::NaiveDateTime;
+
+use serde_json::Value;
+
+#[derive(Debug, Queryable, Identifiable, AsChangeset)]
+#[table_name = "free_companies"]
+crate struct DatabaseFreeCompany {
+  crate id: U64,
+  crate data: Value,
+  crate frecency: f64,
+  crate last_update: NaiveDateTime,
+  /// When the updater that claimed this for refreshing gives up its claim
+  crate claimed_until: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "free_companies"]
+crate struct NewDatabaseFreeCompany {
+  crate id: U64,
+  crate data: Value,
+  crate frecency: f64,
+  crate last_update: NaiveDateTime,
+}
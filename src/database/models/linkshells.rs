@@ -0,0 +1,28 @@
+use crate::database::{
+  models::U64,
+  schema::linkshells,
+};
+
+use chrono::NaiveDateTime;
+
+use serde_json::Value;
+
+#[derive(Debug, Queryable, Identifiable, AsChangeset)]
+#[table_name = "linkshells"]
+crate struct DatabaseLinkshell {
+  crate id: U64,
+  crate data: Value,
+  crate frecency: f64,
+  crate last_update: NaiveDateTime,
+  /// When the updater that claimed this for refreshing gives up its claim
+  crate claimed_until: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "linkshells"]
+crate struct NewDatabaseLinkshell {
+  crate id: U64,
+  crate data: Value,
+  crate frecency: f64,
+  crate last_update: NaiveDateTime,
+}
@@ -0,0 +1,69 @@
+use crate::database::{
+  models::U64,
+  schema::{webhook_deliveries, webhook_subscriptions, webhooks},
+};
+
+use chrono::NaiveDateTime;
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, Queryable, Identifiable)]
+#[table_name = "webhooks"]
+crate struct Webhook {
+  crate id: i64,
+  crate url: String,
+  /// The key payloads sent to the webhook are signed with
+  crate secret: String,
+  crate created: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "webhooks"]
+crate struct NewWebhook<'a> {
+  crate url: &'a str,
+  crate secret: &'a str,
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+#[table_name = "webhook_subscriptions"]
+crate struct WebhookSubscription {
+  crate id: i64,
+  crate webhook_id: i64,
+  /// `character` or `free_company`
+  crate kind: String,
+  crate resource_id: U64,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "webhook_subscriptions"]
+crate struct NewWebhookSubscription<'a> {
+  crate webhook_id: i64,
+  crate kind: &'a str,
+  crate resource_id: U64,
+}
+
+#[derive(Debug, Queryable, Identifiable, Serialize)]
+#[table_name = "webhook_deliveries"]
+pub struct WebhookDelivery {
+  pub id: i64,
+  pub webhook_id: i64,
+  pub payload: Value,
+  /// How many times sending the payload was attempted
+  pub attempts: i32,
+  /// When the payload will next be sent, or none if it was delivered or ran out of attempts
+  pub next_attempt: Option<NaiveDateTime>,
+  /// The HTTP status of the last response from the webhook
+  pub last_status: Option<i32>,
+  /// Why the last attempt failed
+  pub last_error: Option<String>,
+  pub delivered: Option<NaiveDateTime>,
+  pub created: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "webhook_deliveries"]
+crate struct NewWebhookDelivery {
+  crate webhook_id: i64,
+  crate payload: Value,
+  crate next_attempt: Option<NaiveDateTime>,
+}
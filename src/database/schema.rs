@@ -4,5 +4,96 @@ table! {
         data -> Jsonb,
         frecency -> Float8,
         last_update -> Timestamp,
+        name -> Nullable<Text>,
+        world -> Nullable<Text>,
+        claimed_until -> Nullable<Timestamp>,
     }
 }
+
+table! {
+    free_companies (id) {
+        id -> Int8,
+        data -> Jsonb,
+        frecency -> Float8,
+        last_update -> Timestamp,
+        claimed_until -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    linkshells (id) {
+        id -> Int8,
+        data -> Jsonb,
+        frecency -> Float8,
+        last_update -> Timestamp,
+        claimed_until -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    character_snapshots (id) {
+        id -> Int8,
+        character_id -> Int8,
+        data -> Jsonb,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    character_names (id) {
+        id -> Int8,
+        character_id -> Int8,
+        name -> Text,
+        world -> Text,
+        first_seen -> Timestamp,
+        last_seen -> Timestamp,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Int8,
+        url -> Text,
+        secret -> Text,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    webhook_subscriptions (id) {
+        id -> Int8,
+        webhook_id -> Int8,
+        kind -> Text,
+        resource_id -> Int8,
+    }
+}
+
+table! {
+    webhook_deliveries (id) {
+        id -> Int8,
+        webhook_id -> Int8,
+        payload -> Jsonb,
+        attempts -> Int4,
+        next_attempt -> Nullable<Timestamp>,
+        last_status -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        delivered -> Nullable<Timestamp>,
+        created -> Timestamp,
+    }
+}
+
+joinable!(character_names -> characters (character_id));
+joinable!(character_snapshots -> characters (character_id));
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+joinable!(webhook_subscriptions -> webhooks (webhook_id));
+
+allow_tables_to_appear_in_same_query!(
+    character_names,
+    character_snapshots,
+    characters,
+    free_companies,
+    linkshells,
+    webhook_deliveries,
+    webhook_subscriptions,
+    webhooks,
+);
@@ -0,0 +1,144 @@
+use chrono::{DateTime, Utc};
+
+use serde_json::Value;
+
+use std::collections::BTreeSet;
+
+/// A field-level difference between two states of a stored resource, such as a character or free
+/// company.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Diff {
+  /// When the older state was recorded
+  pub from: DateTime<Utc>,
+  /// When the newer state was recorded
+  pub to: DateTime<Utc>,
+  /// Values present only in the newer state
+  pub added: Vec<PathValue>,
+  /// Values present only in the older state
+  pub removed: Vec<PathValue>,
+  /// Values present in both states that differ
+  pub changed: Vec<PathChange>,
+  /// Level changes for each job that gained or lost levels, if the resource has jobs
+  pub job_levels: Vec<LevelDelta>,
+}
+
+/// A value at a path, such as `jobs.Paladin.level`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PathValue {
+  pub path: String,
+  pub value: Value,
+}
+
+/// A changed value at a path.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PathChange {
+  pub path: String,
+  pub from: Value,
+  pub to: Value,
+}
+
+/// A change in a job's level.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LevelDelta {
+  pub job: String,
+  /// The previous level, if the job was unlocked
+  pub from: Option<u64>,
+  /// The new level, if the job is unlocked
+  pub to: Option<u64>,
+  /// The number of levels gained, treating locked jobs as level zero
+  pub delta: i64,
+}
+
+impl Diff {
+  /// Compares two serialised states of a resource.
+  crate fn new(from: (DateTime<Utc>, &Value), to: (DateTime<Utc>, &Value)) -> Self {
+    let mut diff = Diff {
+      from: from.0,
+      to: to.0,
+      added: Vec::new(),
+      removed: Vec::new(),
+      changed: Vec::new(),
+      job_levels: Vec::new(),
+    };
+    diff.compare(String::new(), from.1, to.1);
+    diff.job_levels = job_levels(from.1, to.1);
+    diff
+  }
+
+  fn compare(&mut self, path: String, old: &Value, new: &Value) {
+    match (old, new) {
+      (Value::Object(old), Value::Object(new)) => {
+        let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        for key in keys {
+          let child = join(&path, key);
+          match (old.get(key), new.get(key)) {
+            (Some(o), Some(n)) => self.compare(child, o, n),
+            (Some(o), None) => self.removed.push(PathValue { path: child, value: o.clone() }),
+            (None, Some(n)) => self.added.push(PathValue { path: child, value: n.clone() }),
+            (None, None) => {},
+          }
+        }
+      },
+      // lists of plain values (minions, mounts, etc.) are compared as sets
+      (Value::Array(old), Value::Array(new)) if old.iter().chain(new).all(is_scalar) => {
+        for o in old.iter().filter(|o| !new.contains(o)) {
+          self.removed.push(PathValue { path: path.clone(), value: o.clone() });
+        }
+        for n in new.iter().filter(|n| !old.contains(n)) {
+          self.added.push(PathValue { path: path.clone(), value: n.clone() });
+        }
+      },
+      (Value::Array(old), Value::Array(new)) => {
+        for i in 0..old.len().max(new.len()) {
+          let child = join(&path, &i.to_string());
+          match (old.get(i), new.get(i)) {
+            (Some(o), Some(n)) => self.compare(child, o, n),
+            (Some(o), None) => self.removed.push(PathValue { path: child, value: o.clone() }),
+            (None, Some(n)) => self.added.push(PathValue { path: child, value: n.clone() }),
+            (None, None) => {},
+          }
+        }
+      },
+      (old, new) if old != new => self.changed.push(PathChange {
+        path,
+        from: old.clone(),
+        to: new.clone(),
+      }),
+      _ => {},
+    }
+  }
+}
+
+fn job_levels(old: &Value, new: &Value) -> Vec<LevelDelta> {
+  let jobs = |v: &Value| -> BTreeSet<String> {
+    v["jobs"].as_object().map(|jobs| jobs.keys().cloned().collect()).unwrap_or_default()
+  };
+  let all_jobs: BTreeSet<String> = jobs(old).into_iter().chain(jobs(new)).collect();
+  all_jobs
+    .into_iter()
+    .filter_map(|job| {
+      let from = old["jobs"][&job]["level"].as_u64();
+      let to = new["jobs"][&job]["level"].as_u64();
+      let delta = to.unwrap_or(0) as i64 - from.unwrap_or(0) as i64;
+      if delta == 0 {
+        return None;
+      }
+      Some(LevelDelta { job, from, to, delta })
+    })
+    .collect()
+}
+
+fn join(path: &str, key: &str) -> String {
+  if path.is_empty() {
+    key.to_string()
+  } else {
+    format!("{}.{}", path, key)
+  }
+}
+
+fn is_scalar(value: &Value) -> bool {
+  match *value {
+    Value::Array(_) | Value::Object(_) => false,
+    _ => true,
+  }
+}
@@ -17,6 +17,13 @@ crate fn frecency(current: Option<f64>) -> f64 {
   score.ln_1p() + now_decay
 }
 
+/// An SQL expression computing the same as `frecency` for the `frecency` column of a row, for
+/// updating many rows at once.
+crate fn frecency_sql() -> String {
+  let now_decay = Utc::now().timestamp() as f64 * DECAY;
+  format!("ln(1 + exp(frecency - {:?})) + {:?}", now_decay, now_decay)
+}
+
 // crate fn frecency_score(frecency: f64) -> f64 {
 //   let now_decay = Utc::now().timestamp() as f64 * DECAY;
 
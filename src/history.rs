@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+
+use serde_json::Value;
+
+use std::collections::BTreeSet;
+
+/// The top-level character fields that are tracked in a character's history.
+const FIELDS: &[&str] = &["name", "world", "free_company_id", "title"];
+
+/// A point in a character's history at which at least one tracked field changed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+  /// When the change was observed
+  pub timestamp: DateTime<Utc>,
+  /// The fields that changed
+  pub changes: Vec<Change>,
+}
+
+/// A change to a single field of a character.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Change {
+  /// The field that changed, such as `name` or `jobs.Paladin.level`
+  pub field: String,
+  /// The previous value of the field
+  pub from: Value,
+  /// The new value of the field
+  pub to: Value,
+}
+
+/// Builds a timeline of changes from a character's snapshots, ordered oldest first.
+crate fn timeline(snapshots: &[(DateTime<Utc>, Value)]) -> Vec<HistoryEntry> {
+  snapshots
+    .windows(2)
+    .map(|pair| HistoryEntry {
+      timestamp: pair[1].0,
+      changes: changes(&pair[0].1, &pair[1].1),
+    })
+    .filter(|entry| !entry.changes.is_empty())
+    .collect()
+}
+
+/// Compares the tracked fields of two serialised characters.
+crate fn changes(old: &Value, new: &Value) -> Vec<Change> {
+  let mut changes = Vec::new();
+
+  for field in FIELDS {
+    let (from, to) = (&old[field], &new[field]);
+    if from != to {
+      changes.push(Change {
+        field: field.to_string(),
+        from: from.clone(),
+        to: to.clone(),
+      });
+    }
+  }
+
+  let all_jobs: BTreeSet<&String> = jobs(old).chain(jobs(new)).collect();
+  for job in all_jobs {
+    let (from, to) = (&old["jobs"][job]["level"], &new["jobs"][job]["level"]);
+    if from != to {
+      changes.push(Change {
+        field: format!("jobs.{}.level", job),
+        from: from.clone(),
+        to: to.clone(),
+      });
+    }
+  }
+
+  changes
+}
+
+fn jobs(character: &Value) -> impl Iterator<Item = &String> {
+  character["jobs"].as_object().into_iter().flat_map(|jobs| jobs.keys())
+}
@@ -9,13 +9,19 @@ use bb8_redis::redis::AsyncCommands;
 
 use chrono::{DateTime, Duration, TimeZone, Utc};
 
-use serde::{de::DeserializeOwned, Serialize};
+use serde::de::DeserializeOwned;
 
+pub mod admin;
+pub mod cache;
+mod config;
 pub mod database;
+pub mod diff;
 mod error;
 mod frecency;
+pub mod history;
 pub mod redis;
 pub mod routes;
+pub mod scraper;
 pub mod workers;
 
 use crate::{
@@ -23,29 +29,50 @@ use crate::{
   redis::Redis,
 };
 
+/// Serves a resource of the given `CacheKind` from the Redis cache, or caches the `RouteResult`
+/// produced by a future.
+///
+/// Resources past their soft expiry are served stale while a single background task runs the
+/// future to refresh them, so the future must be `'static`.
 #[macro_export]
 macro_rules! cached {
-  ($runtime:expr, $redis:expr, $key:expr => $bl:block) => {{
+  ($runtime:expr, $redis:expr, $kind:expr, $key:expr => $fut:expr) => {{
     use crate::routes::RouteResult;
-    use chrono::{Duration, TimeZone, Utc};
-    use rocket_contrib::json::Json;
+    use chrono::Utc;
 
-    if let Some((result, expires)) = $runtime.handle().block_on(crate::find_redis(&mut $redis, $key.as_str()))? {
-      return Ok(Json(RouteResult::Cached { result, expires }));
+    let key: String = $key;
+    match $runtime.handle().block_on(crate::cache::find(&$redis, &key))? {
+      Some(entry) if entry.soft_expires > Utc::now() => return Ok(entry.into_result(false)),
+      Some(entry) => {
+        // only the request that takes the lock refreshes the resource
+        if $runtime.handle().block_on(crate::cache::lock_refresh(&$redis, &key))? {
+          let pool = (*$redis).clone();
+          let fut = $fut;
+          $runtime.handle().spawn(async move {
+            if let Err(e) = crate::cache::refresh(&pool, $kind, &key, fut).await {
+              eprintln!("error refreshing {}: {}", key, e);
+            }
+          });
+        }
+        return Ok(entry.into_result(true));
+      },
+      None => {},
     }
-    let res = $bl;
+    let res = $runtime.handle().block_on($fut);
     if let RouteResult::Scraped { result } = res {
-      $runtime.handle().block_on(crate::put_redis(&mut $redis, $key.as_str(), &result))?;
-      let expires = Utc.timestamp((Utc::now() + Duration::seconds(3600)).timestamp(), 0);
-      return Ok(Json(RouteResult::Cached { result, expires }));
+      let entry = $runtime.handle().block_on(crate::cache::put(&$redis, $kind, &key, result))?;
+      return Ok(entry.into_result(false));
     }
-    Ok(Json(res))
+    Ok(res)
   }}
 }
 
 crate async fn find_redis<'a, T>(redis: &mut Redis<'a>, key: &str) -> Result<Option<(T, DateTime<Utc>)>>
 where T: DeserializeOwned,
 {
+  if let Some((x, expires)) = crate::cache::local::get(key) {
+    return Ok(Some((serde_json::from_str(&x)?, expires)));
+  }
   let mut redis = redis.get().await?;
   let json: Option<String> = redis.get(key).await?;
   match json {
@@ -54,17 +81,9 @@ where T: DeserializeOwned,
       let expires_in: i64 = bb8_redis::redis::cmd("PTTL").arg(key).query_async(&mut *redis).await?;
       // we only want second resolution
       let expires = Utc.timestamp((Utc::now() + Duration::milliseconds(expires_in)).timestamp(), 0);
+      crate::cache::local::insert(key, &x, expires);
       Ok(Some((json, expires)))
     },
     None => Ok(None),
   }
 }
-
-crate async fn put_redis<'a, T>(redis: &mut Redis<'a>, key: &str, val: T) -> Result<()>
-where T: Serialize,
-{
-  let mut redis = redis.get().await?;
-  let json = serde_json::to_string(&val)?;
-  redis.set_ex(key, json, 3600).await?;
-  Ok(())
-}
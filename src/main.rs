@@ -2,7 +2,7 @@
 
 #[macro_use] extern crate rocket;
 
-use lodestone_scraper::LodestoneScraper;
+use lodestone_api::scraper::Scraper;
 
 fn main() {
   let runtime = tokio::runtime::Builder::new()
@@ -14,23 +14,45 @@ fn main() {
   let db_pool = lodestone_api::database::pool();
   let redis_pool = runtime.handle().block_on(lodestone_api::redis::pool());
 
-  runtime.enter(|| lodestone_api::workers::queue(&redis_pool, &db_pool));
-  runtime.enter(|| lodestone_api::workers::updater(&db_pool));
+  let scraper = Scraper::new(&redis_pool);
+
+  runtime.enter(lodestone_api::cache::local::listen);
+
+  // workers can be run separately by lodestone_worker
+  if !std::env::args().any(|arg| arg == "--no-workers") {
+    runtime.enter(|| lodestone_api::workers::start(&redis_pool, &db_pool, &scraper));
+  }
 
   rocket::ignite()
     .manage(db_pool)
     .manage(redis_pool)
-    .manage(LodestoneScraper::default())
+    .manage(scraper)
     .manage(runtime)
     .mount("/", routes![
       lodestone_api::routes::index,
       lodestone_api::routes::character::get,
+      lodestone_api::routes::character::refresh,
+      lodestone_api::routes::character::events,
+      lodestone_api::routes::character::get_history,
+      lodestone_api::routes::character::get_diff,
+      lodestone_api::routes::character::lookup,
+      lodestone_api::routes::character::get_many,
       lodestone_api::routes::search::character::get,
+      lodestone_api::routes::search::character::get_local,
       lodestone_api::routes::free_company::get,
       lodestone_api::routes::search::free_company::get,
       lodestone_api::routes::linkshell::get,
       lodestone_api::routes::linkshell::get_page,
       lodestone_api::routes::search::linkshell::get,
+      lodestone_api::routes::admin::dead_letters,
+      lodestone_api::routes::admin::delete_dead_letter,
+      lodestone_api::routes::admin::cache_keys,
+      lodestone_api::routes::admin::cache_entry,
+      lodestone_api::routes::admin::delete_cache_entry,
+      lodestone_api::routes::admin::purge_cache,
+      lodestone_api::routes::webhooks::create_webhook,
+      lodestone_api::routes::webhooks::delete_webhook,
+      lodestone_api::routes::webhooks::deliveries,
     ])
     .launch();
 }
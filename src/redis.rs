@@ -1,6 +1,11 @@
+use crate::error::Result;
+
 use bb8::Pool;
 
-use bb8_redis::RedisConnectionManager;
+use bb8_redis::{
+  RedisConnectionManager,
+  redis::{Client, aio::{Connection, PubSub}},
+};
 
 use rocket::{
   Request, State, Outcome,
@@ -22,6 +27,19 @@ pub async fn pool() -> RedisPool {
     .expect("could not build redis pool")
 }
 
+/// Opens a connection outside the pool, for commands that hold a connection for a long time.
+crate async fn connection() -> Result<Connection> {
+  let url = std::env::var("REDIS_URL")
+    .expect("missing REDIS_URL environment variable");
+  let client = Client::open(url.as_str())?;
+  Ok(client.get_async_connection().await?)
+}
+
+/// Opens a connection for subscribing to pub/sub channels, which can't go back into the pool.
+crate async fn pubsub() -> Result<PubSub> {
+  Ok(connection().await?.into_pubsub())
+}
+
 pub struct Redis<'a>(crate State<'a, RedisPool>);
 
 impl<'a, 'r> FromRequest<'a, 'r> for Redis<'r> {
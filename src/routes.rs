@@ -1,14 +1,38 @@
+use crate::{
+  error::Result,
+  redis::Redis,
+  workers::queue::{self, Priority},
+};
+
 use chrono::{DateTime, Utc};
 
 use lodestone_parser::error::Error as ParserError;
 
 use lodestone_scraper::error::Error;
 
+use rocket::{
+  Request,
+  http::{RawStr, Status},
+  request::FromFormValue,
+  response::{self, Responder, Response},
+};
+
+use rocket_contrib::json::Json;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use sha2::{Digest, Sha256};
+
+use tokio::runtime::Runtime;
+
 use std::fmt::Display;
 
+pub mod admin;
 pub mod character;
+crate mod events;
 pub mod free_company;
 pub mod linkshell;
+pub mod webhooks;
 
 pub mod search;
 
@@ -29,8 +53,11 @@ pub enum RouteResult<T> {
   },
   /// The resource wasn't found, so it has been queued for scraping.
   Adding {
-    /// The position the resource is in its scrape queue
+    /// The resource's place in its lane of the scrape queue, counting from one for the next to be
+    /// scraped, or zero if it is being scraped now
     queue_position: u64,
+    /// Roughly how many seconds until the resource is scraped, if known
+    estimated_wait: Option<u64>,
   },
   /// The resource was scraped once and returned.
   Scraped {
@@ -41,9 +68,13 @@ pub enum RouteResult<T> {
   Cached {
     /// The resource
     result: T,
-    /// When the resource will expire from the cache, after which new requests will result in a new
-    /// scrape
+    /// When the resource was scraped and cached
+    cached_at: DateTime<Utc>,
+    /// When the resource expires from the cache, after which it is served stale while a new scrape
+    /// is made in the background
     expires: DateTime<Utc>,
+    /// Whether the resource has expired and is being scraped again
+    stale: bool,
   },
   /// The resource was not found.
   NotFound,
@@ -51,6 +82,12 @@ pub enum RouteResult<T> {
   Error {
     /// The error message
     error: String,
+    /// The HTTP status to respond with, if not 500
+    #[serde(skip)]
+    status: Option<Status>,
+    /// How many seconds the client should wait before trying again, if it should
+    #[serde(skip)]
+    retry_after: Option<u64>,
   },
 }
 
@@ -58,6 +95,26 @@ impl<T> RouteResult<T> {
   pub fn error<D: Display>(error: D) -> Self {
     RouteResult::Error {
       error: error.to_string(),
+      status: None,
+      retry_after: None,
+    }
+  }
+
+  /// An error that is responded to with the given HTTP status instead of 500.
+  pub fn error_with_status<D: Display>(status: Status, error: D) -> Self {
+    RouteResult::Error {
+      error: error.to_string(),
+      status: Some(status),
+      retry_after: None,
+    }
+  }
+
+  /// A 429 Too Many Requests error telling the client how many seconds to wait before trying again.
+  pub fn too_many_requests<D: Display>(retry_after: u64, error: D) -> Self {
+    RouteResult::Error {
+      error: error.to_string(),
+      status: Some(Status::TooManyRequests),
+      retry_after: Some(retry_after),
     }
   }
 
@@ -70,6 +127,20 @@ impl<T> RouteResult<T> {
     }
   }
 
+  /// Converts the resource in the result, if there is one.
+  pub fn map<U, F>(self, f: F) -> RouteResult<U>
+    where F: FnOnce(T) -> U,
+  {
+    match self {
+      RouteResult::Success { result, last_update } => RouteResult::Success { result: f(result), last_update },
+      RouteResult::Adding { queue_position, estimated_wait } => RouteResult::Adding { queue_position, estimated_wait },
+      RouteResult::Scraped { result } => RouteResult::Scraped { result: f(result) },
+      RouteResult::Cached { result, cached_at, expires, stale } => RouteResult::Cached { result: f(result), cached_at, expires, stale },
+      RouteResult::NotFound => RouteResult::NotFound,
+      RouteResult::Error { error, status, retry_after } => RouteResult::Error { error, status, retry_after },
+    }
+  }
+
   pub fn into_result(self) -> Option<T> {
     match self {
       RouteResult::Success { result, .. }
@@ -85,15 +156,159 @@ impl<T> From<std::result::Result<T, Error>> for RouteResult<T> {
     match res {
       Ok(result) => RouteResult::Scraped { result },
       Err(Error::NotFound) => RouteResult::NotFound,
-      Err(error @ Error::UnexpectedResponse(_)) => RouteResult::error(error),
-      Err(Error::Parse(ParserError::InvalidPage(page))) => RouteResult::error(format!(
+      Err(error @ Error::UnexpectedResponse(_)) => RouteResult::error_with_status(Status::BadGateway, error),
+      Err(Error::Parse(ParserError::InvalidPage(page))) => RouteResult::error_with_status(Status::BadRequest, format!(
         "invalid page (1 through {} available)",
         page,
       )),
       Err(e) => {
         eprintln!("error: {:#?}", e);
-        RouteResult::error("an internal error occurred. did the lodestone change?")
+        RouteResult::error_with_status(Status::BadGateway, "an internal error occurred. did the lodestone change?")
+      },
+    }
+  }
+}
+
+/// How many seconds clients are told to wait before asking again for a queued resource whose wait
+/// can't be estimated.
+const DEFAULT_RETRY_AFTER: u64 = 5;
+
+/// Responds with the result as JSON, with an HTTP status and caching headers matching the variant.
+///
+/// Results carry an `ETag`, and successful responses are replaced with 304 Not Modified when the
+/// request's `If-None-Match` or `If-Modified-Since` shows the client already has them.
+impl<T: Serialize> Responder<'r> for RouteResult<T> {
+  fn respond_to(self, request: &Request) -> response::Result<'r> {
+    let now = Utc::now();
+    let mut headers = Vec::new();
+    let status = match &self {
+      RouteResult::Success { last_update, .. } => {
+        // stored resources can be refreshed at any time, so clients should revalidate
+        headers.push(("Cache-Control", "no-cache".to_string()));
+        headers.push(("Last-Modified", http_date(*last_update)));
+        Status::Ok
+      },
+      RouteResult::Cached { cached_at, expires, stale, .. } => {
+        let max_age = (*expires - now).num_seconds().max(0);
+        headers.push(("Cache-Control", format!("public, max-age={}", max_age)));
+        headers.push(("Expires", http_date(*expires)));
+        headers.push(("Last-Modified", http_date(*cached_at)));
+        if *stale {
+          headers.push(("Warning", "110 - \"Response is Stale\"".to_string()));
+        }
+        Status::Ok
+      },
+      RouteResult::Scraped { .. } => {
+        headers.push(("Cache-Control", "no-cache".to_string()));
+        Status::Ok
+      },
+      RouteResult::Adding { estimated_wait, .. } => {
+        let retry_after = estimated_wait.unwrap_or(DEFAULT_RETRY_AFTER).max(1);
+        headers.push(("Cache-Control", "no-store".to_string()));
+        headers.push(("Retry-After", retry_after.to_string()));
+        Status::Accepted
+      },
+      RouteResult::NotFound => Status::NotFound,
+      RouteResult::Error { status, retry_after, .. } => {
+        headers.push(("Cache-Control", "no-store".to_string()));
+        if let Some(retry_after) = retry_after {
+          headers.push(("Retry-After", retry_after.to_string()));
+        }
+        (*status).unwrap_or(Status::InternalServerError)
       },
+    };
+    let last_modified = match &self {
+      RouteResult::Success { last_update, .. } => Some(*last_update),
+      RouteResult::Cached { cached_at, .. } => Some(*cached_at),
+      _ => None,
+    };
+    let etag = match self.result() {
+      Some(result) => Some(etag(result).map_err(|_| Status::InternalServerError)?),
+      None => None,
+    };
+    let mut response = if status == Status::Ok && not_modified(request, etag.as_ref(), last_modified) {
+      Response::build().status(Status::NotModified).finalize()
+    } else {
+      let mut response = Json(&self).respond_to(request)?;
+      response.set_status(status);
+      response
+    };
+    if let Some(etag) = etag {
+      headers.push(("ETag", etag));
+    }
+    for (name, value) in headers {
+      response.set_raw_header(name, value);
+    }
+    Ok(response)
+  }
+}
+
+/// A strong entity tag for a result: the quoted SHA-256 of its JSON.
+fn etag<T: Serialize>(result: &T) -> Result<String> {
+  let json = serde_json::to_vec(result)?;
+  let hash: String = Sha256::digest(&json).iter().map(|b| format!("{:02x}", b)).collect();
+  Ok(format!("\"{}\"", hash))
+}
+
+/// Checks the request's conditional headers against a result's entity tag and modification date.
+///
+/// `If-Modified-Since` is only used if the request has no `If-None-Match`.
+fn not_modified(request: &Request, etag: Option<&String>, last_modified: Option<DateTime<Utc>>) -> bool {
+  if let Some(if_none_match) = request.headers().get_one("If-None-Match") {
+    let etag = match etag {
+      Some(e) => e,
+      None => return false,
+    };
+    return if_none_match
+      .split(',')
+      .map(|tag| tag.trim())
+      .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag.as_str());
+  }
+  let since = request.headers()
+    .get_one("If-Modified-Since")
+    .and_then(|since| DateTime::parse_from_rfc2822(since).ok());
+  match (since, last_modified) {
+    // http dates only have second resolution
+    (Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+    _ => false,
+  }
+}
+
+/// Formats a date as in HTTP headers, such as `Sun, 06 Nov 1994 08:49:37 GMT`.
+crate fn http_date(date: DateTime<Utc>) -> String {
+  date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+impl FromFormValue<'v> for Priority {
+  type Error = &'v RawStr;
+
+  fn from_form_value(form_value: &'v RawStr) -> std::result::Result<Self, Self::Error> {
+    match form_value.as_str() {
+      "interactive" => Ok(Priority::Interactive),
+      "bulk" => Ok(Priority::Bulk),
+      "background" => Ok(Priority::Background),
+      _ => Err(form_value),
     }
   }
 }
+
+/// Checks Redis for a cached negative result for the resource of the given kind, queueing it for
+/// scraping in the given lane if there is none.
+///
+/// `kind` is the prefix of the Redis keys for the resource, such as `character`. The position
+/// returned is the position within the lane.
+crate fn queued<T>(runtime: &Runtime, pool: &mut Redis, kind: &str, id: u64, priority: Priority) -> Result<RouteResult<T>>
+  where T: DeserializeOwned,
+{
+  // find result in redis and return it if present
+  if let Ok(Some((rr, _))) = runtime.handle().block_on(crate::find_redis(pool, &format!("{}_{}", kind, id))) {
+    return Ok(rr);
+  }
+  let mut redis = runtime.handle().block_on(pool.get())?;
+  // if not, add it to the queue and return its position
+  let pos = runtime.handle().block_on(queue::enqueue(&mut *redis, kind, id, priority))?;
+  Ok(RouteResult::Adding {
+    queue_position: pos.position,
+    estimated_wait: pos.estimated_wait,
+  })
+}
@@ -0,0 +1,168 @@
+use crate::{
+  cache::{self, CacheKind, SCHEMA_VERSION},
+  config::CONFIG,
+  error::*,
+  redis::Redis,
+  workers::queue::{DEAD_LETTERS, DeadLetter},
+};
+
+use bb8_redis::redis::{self, AsyncCommands};
+
+use rocket::{
+  Request, State, Outcome,
+  http::Status,
+  request::{self, FromRequest},
+};
+
+use rocket_contrib::json::Json;
+
+use serde_json::Value;
+
+use tokio::runtime::Runtime;
+
+/// A request guard for requests carrying the admin token as a bearer token.
+pub struct Admin;
+
+impl FromRequest<'a, 'r> for Admin {
+  type Error = ();
+
+  fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+    // admin routes don't exist without a token
+    let token = match CONFIG.admin_token {
+      Some(ref t) => t,
+      None => return Outcome::Failure((Status::NotFound, ())),
+    };
+    let given = request.headers()
+      .get_one("Authorization")
+      .and_then(|auth| auth.strip_prefix("Bearer "));
+    match given {
+      Some(t) if t == token.as_str() => Outcome::Success(Admin),
+      _ => Outcome::Failure((Status::Unauthorized, ())),
+    }
+  }
+}
+
+/// Lists the resources that failed to scrape too many times, most recent first.
+#[get("/admin/dead_letters")]
+pub fn dead_letters(_admin: Admin, pool: Redis, runtime: State<Runtime>) -> Result<Json<Vec<DeadLetter>>> {
+  let mut redis = runtime.handle().block_on(pool.get())?;
+  let entries: Vec<String> = runtime.handle().block_on(redis.hvals(DEAD_LETTERS))?;
+  let mut letters = entries
+    .iter()
+    .map(|e| serde_json::from_str(e))
+    .collect::<std::result::Result<Vec<DeadLetter>, _>>()?;
+  letters.sort_by(|a, b| b.failed_at.cmp(&a.failed_at));
+  Ok(Json(letters))
+}
+
+/// Removes a resource from the dead-letter queue, returning whether it was there.
+#[delete("/admin/dead_letters/<kind>/<id>")]
+pub fn delete_dead_letter(_admin: Admin, kind: String, id: u64, pool: Redis, runtime: State<Runtime>) -> Result<Json<bool>> {
+  let mut redis = runtime.handle().block_on(pool.get())?;
+  let removed: u64 = runtime.handle().block_on(redis.hdel(DEAD_LETTERS, format!("{}_{}", kind, id)))?;
+  Ok(Json(removed > 0))
+}
+
+/// How many keys `cache_keys` lists at most.
+const MAX_LISTED_KEYS: usize = 1000;
+
+/// A key in the cache and how long until it expires.
+#[derive(Debug, Serialize)]
+pub struct CachedKey {
+  pub key: String,
+  /// Milliseconds until the entry is removed
+  pub ttl: i64,
+}
+
+/// A cached resource as it is stored.
+#[derive(Debug, Serialize)]
+pub struct CachedEntry {
+  pub key: String,
+  /// Milliseconds until the entry is removed
+  pub ttl: i64,
+  pub entry: Value,
+}
+
+/// Finds the pattern matching the keys a query asks for, or none if its kind is unknown.
+///
+/// Kinds are the names of `CacheKind`s, or `<kind>_not_found` for the negative results the queue
+/// worker caches. Negative results aren't versioned, and aren't included when no kind is given.
+fn cache_pattern(kind: Option<String>, id: Option<u64>, version: Option<u32>) -> Option<String> {
+  let version = version.unwrap_or(SCHEMA_VERSION);
+  let kind = match kind {
+    Some(k) => k,
+    None => return Some(cache::pattern(version, None, id)),
+  };
+  if let Some(kind) = CacheKind::from_name(&kind) {
+    return Some(cache::pattern(version, Some(kind), id));
+  }
+  cache::NOT_FOUND_KINDS.iter()
+    .find(|&&k| kind.strip_suffix("_not_found") == Some(k))
+    .map(|k| cache::not_found_pattern(k, id))
+}
+
+async fn pttl(redis: &mut redis::aio::Connection, key: &str) -> Result<i64> {
+  Ok(redis::cmd("PTTL").arg(key).query_async(redis).await?)
+}
+
+/// Lists cached resources by kind and ID, in the current schema version unless another is given.
+#[get("/admin/cache?<kind>&<id>&<version>")]
+pub fn cache_keys(_admin: Admin, kind: Option<String>, id: Option<u64>, version: Option<u32>, pool: Redis, runtime: State<Runtime>) -> Result<Option<Json<Vec<CachedKey>>>> {
+  let pattern = match cache_pattern(kind, id, version) {
+    Some(p) => p,
+    None => return Ok(None),
+  };
+  runtime.handle().block_on(async {
+    let keys = cache::keys(&pool, &pattern, MAX_LISTED_KEYS).await?;
+    let mut redis = pool.get().await?;
+    let mut listed = Vec::with_capacity(keys.len());
+    for key in keys {
+      let ttl = pttl(&mut *redis, &key).await?;
+      listed.push(CachedKey { key, ttl });
+    }
+    Ok(Some(Json(listed)))
+  })
+}
+
+/// Shows a cached resource as it is stored. Keys that aren't in the cache are a bad request.
+#[get("/admin/cache/entry?<key>")]
+pub fn cache_entry(_admin: Admin, key: String, pool: Redis, runtime: State<Runtime>) -> Result<std::result::Result<Option<Json<CachedEntry>>, Status>> {
+  if !cache::is_cached(&key) {
+    return Ok(Err(Status::BadRequest));
+  }
+  runtime.handle().block_on(async {
+    let mut redis = pool.get().await?;
+    let json: Option<String> = redis.get(&key).await?;
+    let entry = match json {
+      Some(j) => serde_json::from_str(&j)?,
+      None => return Ok(Ok(None)),
+    };
+    let ttl = pttl(&mut *redis, &key).await?;
+    Ok(Ok(Some(Json(CachedEntry { key, ttl, entry }))))
+  })
+}
+
+/// Removes a cached resource, returning whether it was cached. Keys that aren't in the cache are a
+/// bad request, so the queues can't be removed by mistake.
+#[delete("/admin/cache/entry?<key>")]
+pub fn delete_cache_entry(_admin: Admin, key: String, pool: Redis, runtime: State<Runtime>) -> Result<std::result::Result<Json<bool>, Status>> {
+  if !cache::is_cached(&key) {
+    return Ok(Err(Status::BadRequest));
+  }
+  let mut redis = runtime.handle().block_on(pool.get())?;
+  let removed: u64 = runtime.handle().block_on(redis.del(&key))?;
+  runtime.handle().block_on(cache::local::invalidate(&mut *redis, &[key]))?;
+  Ok(Ok(Json(removed > 0)))
+}
+
+/// Removes cached resources by kind and ID, returning how many were removed. Without a kind, every
+/// resource in the schema version is removed, which is the current version unless another is given.
+#[delete("/admin/cache?<kind>&<id>&<version>")]
+pub fn purge_cache(_admin: Admin, kind: Option<String>, id: Option<u64>, version: Option<u32>, pool: Redis, runtime: State<Runtime>) -> Result<Option<Json<u64>>> {
+  let pattern = match cache_pattern(kind, id, version) {
+    Some(p) => p,
+    None => return Ok(None),
+  };
+  let removed = runtime.handle().block_on(cache::purge(&pool, &pattern))?;
+  Ok(Some(Json(removed)))
+}
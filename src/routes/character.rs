@@ -1,30 +1,68 @@
 use crate::{
+  config::CONFIG,
   error::*,
   database::{
     DbConn,
-    models::{U64, characters::DatabaseCharacter},
-    schema::characters,
+    PostgresPool,
+    models::{
+      U64,
+      character_names::CharacterName,
+      character_snapshots::CharacterSnapshot,
+      characters::DatabaseCharacter,
+    },
+    schema::{character_names, character_snapshots, characters},
   },
-  redis::Redis,
-  routes::RouteResult,
+  diff::Diff,
+  history::HistoryEntry,
+  redis::{Redis, RedisPool},
+  routes::{
+    RouteResult,
+    events::EventStream,
+    search::character::{self as character_search, CharacterSearchData},
+  },
+  scraper::Scraper,
+  workers::queue::{self, Completion, Priority},
 };
 
-use bb8_redis::redis::AsyncCommands;
+use bb8_redis::redis::{self, AsyncCommands, aio::{Connection, PubSub}};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 
-use chrono::{TimeZone, Utc};
+use diesel::{
+  dsl::sql,
+  prelude::*,
+  sql_types::{Bool, Float8, Text},
+};
 
-use diesel::prelude::*;
+use ffxiv_types::World;
 
 use lodestone_parser::models::character::Character;
 
-use rocket::State;
+use rocket::{State, http::Status, request::Form};
 
 use rocket_contrib::json::Json;
 
-use tokio::runtime::Runtime;
+use tokio::runtime::{Handle, Runtime};
 
-#[get("/character/<id>")]
-pub fn get(id: u64, conn: DbConn, mut pool: Redis, runtime: State<Runtime>) -> Result<Json<RouteResult<Character>>> {
+use std::{
+  collections::HashMap,
+  io::{self, Read},
+  str::FromStr,
+  sync::atomic::{AtomicUsize, Ordering},
+  time::{Duration, Instant},
+};
+
+sql_function!(fn lower(x: Text) -> Text);
+
+/// Gets a stored character, queueing it for scraping in the given lane (interactive by default) if
+/// it isn't stored.
+///
+/// If `wait` is given, waits up to that many seconds (capped by `MAX_WAIT`) for a queued character
+/// to be scraped, returning the outcome instead of its position if it finishes in time. Waiting
+/// holds a Rocket worker, so at most `MAX_WAITERS` lookups wait at once and the rest get the
+/// position straight away.
+#[get("/character/<id>?<priority>&<wait>")]
+pub fn get(id: u64, priority: Option<Priority>, wait: Option<u64>, conn: DbConn, db_pool: State<PostgresPool>, mut pool: Redis, runtime: State<Runtime>) -> Result<RouteResult<Character>> {
   // get character stored in database
   let db_char: Option<DatabaseCharacter> = characters::table
     .find(U64(id))
@@ -38,22 +76,522 @@ pub fn get(id: u64, conn: DbConn, mut pool: Redis, runtime: State<Runtime>) -> R
       .set(characters::frecency.eq(new_frecency))
       .filter(characters::id.eq(dbc.id))
       .execute(&*conn)?;
-    return Ok(Json(RouteResult::Success {
+    return Ok(RouteResult::Success {
       result: c,
       last_update: Utc.from_utc_datetime(&dbc.last_update),
+    });
+  }
+  // don't hold the connection while waiting
+  drop(conn);
+  let wait = wait.unwrap_or(0).min(CONFIG.max_wait);
+  let slot = match wait {
+    0 => None,
+    _ => Slot::take(&WAITERS, CONFIG.max_waiters),
+  };
+  if slot.is_none() {
+    // otherwise check for a negative result or queue the character without waiting
+    return crate::routes::queued(&runtime, &mut pool, "character", id, priority.unwrap_or(Priority::Interactive));
+  }
+  // subscribe before queueing so that the completion can't be missed
+  let mut pubsub = runtime.handle().block_on(queue::subscribe(&["character"], id))?;
+  let queued = crate::routes::queued(&runtime, &mut pool, "character", id, priority.unwrap_or(Priority::Interactive))?;
+  match queued {
+    RouteResult::Adding { .. } => {},
+    _ => return Ok(queued),
+  }
+  let completion = runtime.handle().block_on(queue::wait_for_completion(&mut pubsub, Duration::from_secs(wait)))?;
+  let result = match completion {
+    Some(Completion::Success) => finished(runtime.handle(), &db_pool, &pool, id)?,
+    Some(Completion::NotFound) => Some(RouteResult::NotFound),
+    Some(Completion::Error { error }) => Some(RouteResult::error_with_status(Status::BadGateway, error)),
+    None => None,
+  };
+  if let Some(result) = result {
+    return Ok(result);
+  }
+  // still waiting, so give the up-to-date position
+  let mut redis = runtime.handle().block_on(pool.get())?;
+  match runtime.handle().block_on(queue::position(&mut *redis, "character", id))? {
+    Some(pos) => Ok(adding(pos)),
+    None => Ok(queued),
+  }
+}
+
+/// Queues a stored character to be scraped again ahead of the updater, at most once per
+/// `REFRESH_COOLDOWN` seconds. Characters that aren't stored are queued as by `get`.
+#[post("/character/<id>/refresh")]
+pub fn refresh(id: u64, conn: DbConn, mut pool: Redis, runtime: State<Runtime>) -> Result<RouteResult<Character>> {
+  let stored: Option<U64> = characters::table
+    .find(U64(id))
+    .select(characters::id)
+    .get_result(&*conn)
+    .optional()?;
+  if stored.is_none() {
+    return crate::routes::queued(&runtime, &mut pool, "character", id, Priority::Interactive);
+  }
+  let mut redis = runtime.handle().block_on(pool.get())?;
+  // asking again while the refresh is queued just returns its position
+  if let Some(pos) = runtime.handle().block_on(queue::position(&mut *redis, "character_refresh", id))? {
+    return Ok(adding(pos));
+  }
+  let cooldown_key = format!("character_refresh_cooldown_{}", id);
+  let started: Option<String> = runtime.handle().block_on(
+    redis::cmd("SET")
+      .arg(&cooldown_key)
+      .arg(1)
+      .arg("NX")
+      .arg("EX")
+      .arg(CONFIG.refresh_cooldown)
+      .query_async(&mut *redis),
+  )?;
+  if started.is_none() {
+    let ttl: i64 = runtime.handle().block_on(redis.ttl(&cooldown_key))?;
+    let ttl = ttl.max(1) as u64;
+    return Ok(RouteResult::too_many_requests(ttl, format!(
+      "the character was refreshed recently. try again in {} seconds",
+      ttl,
+    )));
+  }
+  let pos = runtime.handle().block_on(queue::enqueue(&mut *redis, "character_refresh", id, Priority::Interactive))?;
+  Ok(adding(pos))
+}
+
+/// The kinds of queue a character can be waiting in.
+const QUEUE_KINDS: &[&str] = &["character", "character_refresh"];
+
+/// How long the event stream waits for a scrape to complete before checking the queue position.
+const EVENT_POLL_SECS: u64 = 3;
+/// How many polls without any events the event stream waits before keeping the connection alive.
+const KEEP_ALIVE_POLLS: u32 = 10;
+
+/// How many character event streams are open.
+static OPEN_STREAMS: AtomicUsize = AtomicUsize::new(0);
+/// How many character lookups are waiting for scrapes to finish.
+static WAITERS: AtomicUsize = AtomicUsize::new(0);
+
+/// One of a limited number of requests that hold a Rocket worker while waiting on the queue, given
+/// back when dropped.
+struct Slot(&'static AtomicUsize);
+
+impl Slot {
+  /// Takes a slot if fewer than `max` are taken.
+  fn take(taken: &'static AtomicUsize, max: usize) -> Option<Slot> {
+    if taken.fetch_add(1, Ordering::SeqCst) >= max {
+      taken.fetch_sub(1, Ordering::SeqCst);
+      return None;
+    }
+    Some(Slot(taken))
+  }
+}
+
+impl Drop for Slot {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+/// Finds the position of a character in the scrape or refresh queue, if it is in either.
+async fn queue_position(redis: &mut Connection, id: u64) -> Result<Option<queue::Position>> {
+  for kind in QUEUE_KINDS {
+    if let Some(pos) = queue::position(redis, kind, id).await? {
+      return Ok(Some(pos));
+    }
+  }
+  Ok(None)
+}
+
+/// Finds the outcome of a finished scrape of a character: the stored character or a cached negative
+/// result.
+///
+/// A connection is only checked out of the pool while looking, so that waiting for the scrape to
+/// finish doesn't hold one.
+fn finished(handle: &Handle, db_pool: &PostgresPool, redis_pool: &RedisPool, id: u64) -> Result<Option<RouteResult<Character>>> {
+  let db_char: Option<DatabaseCharacter> = characters::table
+    .find(U64(id))
+    .get_result(&*db_pool.get()?)
+    .optional()?;
+  if let Some(dbc) = db_char {
+    return Ok(Some(RouteResult::Success {
+      result: serde_json::from_value(dbc.data)?,
+      last_update: Utc.from_utc_datetime(&dbc.last_update),
     }));
   }
-  // otherwise find result in redis and return it if present
-  if let Ok(Some((rr, _))) = runtime.handle().block_on(crate::find_redis(&mut pool, &format!("character_{}", id))) {
-    return Ok(Json(rr));
+  let mut redis = handle.block_on(redis_pool.get())?;
+  let cached: Option<String> = handle.block_on(redis.get(format!("character_{}", id)))?;
+  match cached {
+    Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+    None => Ok(None),
+  }
+}
+
+fn adding(pos: queue::Position) -> RouteResult<Character> {
+  RouteResult::Adding {
+    queue_position: pos.position,
+    estimated_wait: pos.estimated_wait,
+  }
+}
+
+/// Streams `position` events as a character moves through the queue, then a `result` event with
+/// the outcome of its scrape. Characters that aren't queued or stored are queued first.
+///
+/// Each stream holds a Rocket worker, so at most `MAX_STREAMS` can be open at once and more get a
+/// 503. Streams are closed after `MAX_STREAM_SECS` with a last `position` event, after which
+/// clients can reconnect.
+///
+/// Every event and keep-alive is padded to 8 KiB so that it is sent right away (see
+/// `routes::events`), so clients should expect 8 KiB for each position change, plus 8 KiB every 30
+/// seconds while idle.
+#[get("/character/<id>/events")]
+pub fn events(id: u64, db_pool: State<PostgresPool>, pool: Redis, runtime: State<Runtime>) -> Result<std::result::Result<EventStream<CharacterEvents>, RouteResult<Character>>> {
+  let slot = match Slot::take(&OPEN_STREAMS, CONFIG.max_streams) {
+    Some(s) => s,
+    None => return Ok(Err(RouteResult::error_with_status(Status::ServiceUnavailable, "too many event streams are open. try again later"))),
+  };
+  let handle = runtime.handle().clone();
+  // subscribe before looking at the queue so that a completion in between isn't missed
+  let pubsub = handle.block_on(queue::subscribe(QUEUE_KINDS, id))?;
+  let mut events = CharacterEvents {
+    handle,
+    db_pool: (*db_pool).clone(),
+    redis_pool: (*pool).clone(),
+    pubsub: Some(pubsub),
+    _slot: slot,
+    closes_at: Instant::now() + Duration::from_secs(CONFIG.max_stream_secs),
+    id,
+    last_position: None,
+    idle_polls: 0,
+    pending: Vec::new(),
+  };
+  events.start()?;
+  Ok(Ok(crate::routes::events::stream(events)))
+}
+
+/// The events of a character's scrape, read by `events`.
+pub struct CharacterEvents {
+  handle: Handle,
+  db_pool: PostgresPool,
+  redis_pool: RedisPool,
+  /// The subscription to the character's completions, or none once the stream is closing
+  pubsub: Option<PubSub>,
+  /// This stream's place among the open streams
+  _slot: Slot,
+  /// When the stream is closed even if the scrape hasn't finished
+  closes_at: Instant,
+  id: u64,
+  last_position: Option<u64>,
+  idle_polls: u32,
+  /// Encoded events that haven't been read yet
+  pending: Vec<u8>,
+}
+
+impl CharacterEvents {
+  fn start(&mut self) -> Result<()> {
+    let redis_pool = self.redis_pool.clone();
+    let mut redis = self.handle.block_on(redis_pool.get())?;
+    if let Some(pos) = self.handle.block_on(queue_position(&mut *redis, self.id))? {
+      return self.position(pos);
+    }
+    if let Some(result) = finished(&self.handle, &self.db_pool, &self.redis_pool, self.id)? {
+      return self.result(&result);
+    }
+    let pos = self.handle.block_on(queue::enqueue(&mut *redis, "character", self.id, Priority::Interactive))?;
+    self.position(pos)
+  }
+
+  /// Waits for the next event, leaving `pending` empty if there was none.
+  fn next(&mut self) -> Result<()> {
+    let pubsub = match self.pubsub {
+      Some(ref mut p) => p,
+      None => return Ok(()),
+    };
+    let poll = Duration::from_secs(EVENT_POLL_SECS);
+    let result = match self.handle.block_on(queue::wait_for_completion(pubsub, poll))? {
+      Some(Completion::Success) => finished(&self.handle, &self.db_pool, &self.redis_pool, self.id)?,
+      Some(Completion::NotFound) => Some(RouteResult::NotFound),
+      Some(Completion::Error { error }) => Some(RouteResult::error_with_status(Status::BadGateway, error)),
+      None => None,
+    };
+    if let Some(result) = result {
+      return self.result(&result);
+    }
+    let redis_pool = self.redis_pool.clone();
+    let mut redis = self.handle.block_on(redis_pool.get())?;
+    let closing = Instant::now() >= self.closes_at;
+    match self.handle.block_on(queue_position(&mut *redis, self.id))? {
+      // the stream has been open too long, so give the last position and close
+      Some(pos) if closing => {
+        self.position(pos)?;
+        self.pubsub = None;
+        return Ok(());
+      },
+      Some(ref pos) if Some(pos.position) == self.last_position => {},
+      Some(pos) => return self.position(pos),
+      // the character left the queue while we weren't subscribed to its kind of queue
+      None => if let Some(result) = finished(&self.handle, &self.db_pool, &self.redis_pool, self.id)? {
+        return self.result(&result);
+      },
+    }
+    if closing {
+      self.pubsub = None;
+      return Ok(());
+    }
+    self.idle_polls += 1;
+    if self.idle_polls >= KEEP_ALIVE_POLLS {
+      self.idle_polls = 0;
+      self.pending = crate::routes::events::keep_alive();
+    }
+    Ok(())
+  }
+
+  fn position(&mut self, pos: queue::Position) -> Result<()> {
+    self.last_position = Some(pos.position);
+    self.idle_polls = 0;
+    self.pending = crate::routes::events::event("position", &adding(pos))?;
+    Ok(())
+  }
+
+  fn result(&mut self, result: &RouteResult<Character>) -> Result<()> {
+    self.pubsub = None;
+    self.pending = crate::routes::events::event("result", result)?;
+    Ok(())
+  }
+}
+
+impl Read for CharacterEvents {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.pending.is_empty() {
+      if self.pubsub.is_none() {
+        return Ok(0);
+      }
+      self.next().map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    }
+    let n = buf.len().min(self.pending.len());
+    buf[..n].copy_from_slice(&self.pending[..n]);
+    self.pending.drain(..n);
+    Ok(n)
+  }
+}
+
+/// Gets the timeline of changes to a stored character.
+#[get("/character/<id>/history")]
+pub fn get_history(id: u64, conn: DbConn) -> Result<RouteResult<Vec<HistoryEntry>>> {
+  let last_update: Option<NaiveDateTime> = characters::table
+    .find(U64(id))
+    .select(characters::last_update)
+    .get_result(&*conn)
+    .optional()?;
+  let last_update = match last_update {
+    Some(l) => l,
+    None => return Ok(RouteResult::NotFound),
+  };
+  let snapshots: Vec<CharacterSnapshot> = character_snapshots::table
+    .filter(character_snapshots::character_id.eq(U64(id)))
+    .order(character_snapshots::created.asc())
+    .load(&*conn)?;
+  let snapshots: Vec<_> = snapshots
+    .into_iter()
+    .map(|s| (Utc.from_utc_datetime(&s.created), s.data))
+    .collect();
+  Ok(RouteResult::Success {
+    result: crate::history::timeline(&snapshots),
+    last_update: Utc.from_utc_datetime(&last_update),
+  })
+}
+
+/// Gets the differences between the states a stored character was in at two points in time.
+#[get("/character/<id>/diff?<data..>")]
+pub fn get_diff(id: u64, data: Form<DiffData>, conn: DbConn) -> Result<RouteResult<Diff>> {
+  let last_update: Option<NaiveDateTime> = characters::table
+    .find(U64(id))
+    .select(characters::last_update)
+    .get_result(&*conn)
+    .optional()?;
+  let last_update = match last_update {
+    Some(l) => l,
+    None => return Ok(RouteResult::NotFound),
+  };
+  // find the snapshot that was current at each timestamp
+  let snapshot_at = |ts: i64| -> Result<Option<CharacterSnapshot>> {
+    let snapshot = character_snapshots::table
+      .filter(character_snapshots::character_id.eq(U64(id)))
+      .filter(character_snapshots::created.le(Utc.timestamp(ts, 0).naive_utc()))
+      .order(character_snapshots::created.desc())
+      .first(&*conn)
+      .optional()?;
+    Ok(snapshot)
+  };
+  let (from, to) = match (snapshot_at(data.from)?, snapshot_at(data.to)?) {
+    (Some(from), Some(to)) => (from, to),
+    _ => return Ok(RouteResult::error_with_status(Status::NotFound, "no data was stored for the character at that time")),
+  };
+  Ok(RouteResult::Success {
+    result: Diff::new(
+      (Utc.from_utc_datetime(&from.created), &from.data),
+      (Utc.from_utc_datetime(&to.created), &to.data),
+    ),
+    last_update: Utc.from_utc_datetime(&last_update),
+  })
+}
+
+#[derive(Debug, FromForm)]
+pub struct DiffData {
+  /// Unix timestamp of the older state
+  from: i64,
+  /// Unix timestamp of the newer state
+  to: i64,
+}
+
+/// Resolves a current or previous name and world to the characters that have used them, falling back
+/// to a Lodestone search if no stored character matches. The search is cached along with the same
+/// search made through `/character/search`.
+#[get("/character/lookup?<data..>")]
+pub fn lookup(data: Form<LookupData>, conn: DbConn, scraper: State<Scraper>, redis: Redis, runtime: State<Runtime>) -> Result<RouteResult<Vec<CharacterLookup>>> {
+  let data = data.into_inner();
+  // store worlds the same way they are serialised in character data
+  let world = data.world.map(|w| match World::from_str(&w) {
+    Ok(world) => world.as_str().to_string(),
+    Err(_) => w,
+  });
+
+  let mut query = character_names::table
+    .inner_join(characters::table)
+    .filter(lower(character_names::name).eq(data.name.to_lowercase()))
+    .select((
+      character_names::all_columns,
+      sql::<Bool>("characters.data->>'name' = character_names.name and characters.data->>'world' = character_names.world"),
+    ))
+    .order(character_names::last_seen.desc())
+    .into_boxed();
+  if let Some(ref world) = world {
+    query = query.filter(character_names::world.eq(world));
+  }
+  let names: Vec<(CharacterName, bool)> = query.load(&*conn)?;
+
+  if let Some(last_seen) = names.first().map(|(name, _)| name.last_seen) {
+    let result = names
+      .into_iter()
+      .map(|(name, current)| CharacterLookup {
+        id: *name.character_id,
+        name: name.name,
+        world: name.world,
+        current,
+        last_seen: Some(Utc.from_utc_datetime(&name.last_seen)),
+      })
+      .collect();
+    return Ok(RouteResult::Success {
+      result,
+      last_update: Utc.from_utc_datetime(&last_seen),
+    });
+  }
+
+  // don't hold on to the connection while scraping
+  drop(conn);
+  let search = CharacterSearchData::by_name(data.name, world);
+  let res = character_search::search(search, &scraper, redis, &runtime)?;
+  Ok(res.map(|page| page.results
+    .into_iter()
+    .map(|item| CharacterLookup {
+      id: item.id,
+      name: item.name,
+      world: item.world.as_str().to_string(),
+      current: true,
+      last_seen: None,
+    })
+    .collect()))
+}
+
+#[derive(Debug, FromForm)]
+pub struct LookupData {
+  name: String,
+  world: Option<String>,
+}
+
+/// A character that has used a name on a world.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CharacterLookup {
+  /// The character's Lodestone ID
+  pub id: u64,
+  pub name: String,
+  pub world: String,
+  /// If the character is currently using this name and world
+  pub current: bool,
+  /// When the character was last seen using this name and world, if it is stored
+  pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// The most characters that can be requested at once from `get_many`.
+const MAX_BULK: usize = 200;
+
+/// Gets many characters at once, queueing any that aren't stored in the bulk lane, or the background
+/// lane if asked. Asking for the interactive lane gives the bulk lane, so that imports can't starve
+/// single lookups.
+///
+/// The request body is a JSON array of character IDs. The response maps each ID to the result of
+/// looking it up.
+#[post("/characters?<priority>", format = "json", data = "<ids>")]
+pub fn get_many(ids: Json<Vec<u64>>, priority: Option<Priority>, conn: DbConn, pool: Redis, runtime: State<Runtime>) -> Result<Json<HashMap<u64, RouteResult<Character>>>> {
+  let priority = match priority {
+    Some(Priority::Background) => Priority::Background,
+    _ => Priority::Bulk,
+  };
+  let mut ids = ids.into_inner();
+  ids.sort();
+  ids.dedup();
+  let mut results = HashMap::with_capacity(ids.len());
+  if ids.len() > MAX_BULK {
+    for id in ids.split_off(MAX_BULK) {
+      results.insert(id, RouteResult::error(format!("too many characters requested (max {})", MAX_BULK)));
+    }
+  }
+
+  // get every stored character in one query and bump their frecencies
+  let db_chars: Vec<DatabaseCharacter> = characters::table
+    .filter(characters::id.eq_any(ids.iter().map(|&id| U64(id)).collect::<Vec<_>>()))
+    .load(&*conn)?;
+  diesel::update(characters::table)
+    .set(characters::frecency.eq(sql::<Float8>(&crate::frecency::frecency_sql())))
+    .filter(characters::id.eq_any(db_chars.iter().map(|c| c.id).collect::<Vec<_>>()))
+    .execute(&*conn)?;
+  for dbc in db_chars {
+    let c: Character = serde_json::from_value(dbc.data)?;
+    results.insert(*dbc.id, RouteResult::Success {
+      result: c,
+      last_update: Utc.from_utc_datetime(&dbc.last_update),
+    });
+  }
+
+  let missing: Vec<u64> = ids.into_iter().filter(|id| !results.contains_key(id)).collect();
+  if missing.is_empty() {
+    return Ok(Json(results));
   }
   let mut redis = runtime.handle().block_on(pool.get())?;
-  // if not, add it to the queue
-  if let Some(idx) = runtime.handle().block_on(redis.hget("character_queue_hash", id))? {
-    return Ok(Json(RouteResult::Adding { queue_position: idx }));
-  }
-  let pos: u64 = runtime.handle().block_on(redis.rpush("character_queue", id))?;
-  runtime.handle().block_on(redis.hset("character_queue_hash", id, pos))?;
-  // return position in queue
-  Ok(Json(RouteResult::Adding { queue_position: pos }))
+
+  // use cached negative results where there are any
+  let keys: Vec<String> = missing.iter().map(|id| format!("character_{}", id)).collect();
+  let cached: Vec<Option<String>> = runtime.handle().block_on(
+    redis::cmd("MGET").arg(&keys).query_async(&mut *redis),
+  )?;
+  let mut to_queue = Vec::with_capacity(missing.len());
+  for (id, cached) in missing.into_iter().zip(cached) {
+    match cached {
+      Some(json) => { results.insert(id, serde_json::from_str(&json)?); },
+      None => to_queue.push(id),
+    }
+  }
+
+  // and queue the rest all at once
+  let positions = runtime.handle().block_on(queue::enqueue_many(
+    &mut *redis,
+    "character",
+    &to_queue,
+    priority,
+  ))?;
+  for (id, pos) in positions {
+    results.insert(id, RouteResult::Adding {
+      queue_position: pos.position,
+      estimated_wait: pos.estimated_wait,
+    });
+  }
+
+  Ok(Json(results))
 }
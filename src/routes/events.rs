@@ -0,0 +1,47 @@
+//! Helpers for routes that stream server-sent events.
+
+use crate::error::*;
+
+use rocket::{
+  http::ContentType,
+  response::{Content, Stream},
+};
+
+use serde::Serialize;
+
+use std::io::Read;
+
+/// The size of the chunks event streams are written in.
+///
+/// Rocket only writes a chunk once it has read a full one, and the connection's 8 KiB write buffer
+/// only flushes once it is full, so every event is padded to a multiple of this size to be sent as
+/// soon as it is read.
+const CHUNK_SIZE: usize = 8192;
+
+/// A stream of server-sent events read from `R`.
+pub type EventStream<R> = Content<Stream<R>>;
+
+crate fn stream<R: Read>(events: R) -> EventStream<R> {
+  Content(ContentType::new("text", "event-stream"), Stream::chunked(events, CHUNK_SIZE as u64))
+}
+
+/// Encodes an event with the given name and JSON data, padded with a comment.
+crate fn event<T: Serialize>(name: &str, data: &T) -> Result<Vec<u8>> {
+  let event = format!("event: {}\ndata: {}\n\n", name, serde_json::to_string(data)?);
+  Ok(pad(event))
+}
+
+/// Encodes an empty comment that keeps idle connections open.
+crate fn keep_alive() -> Vec<u8> {
+  pad(String::new())
+}
+
+fn pad(mut event: String) -> Vec<u8> {
+  // the comment is at least ":\n"
+  let len = event.len() + 2;
+  let padded = (len + CHUNK_SIZE - 1) / CHUNK_SIZE * CHUNK_SIZE;
+  event.push(':');
+  event.extend(std::iter::repeat(' ').take(padded - len));
+  event.push('\n');
+  event.into_bytes()
+}
@@ -1,24 +1,45 @@
 use crate::{
-  cached,
   error::*,
+  database::{
+    DbConn,
+    models::{U64, free_companies::DatabaseFreeCompany},
+    schema::free_companies,
+  },
   redis::Redis,
   routes::RouteResult,
+  workers::queue::Priority,
 };
 
-use lodestone_parser::models::free_company::FreeCompany;
+use chrono::{TimeZone, Utc};
 
-use lodestone_scraper::LodestoneScraper;
+use diesel::prelude::*;
 
-use rocket::State;
+use lodestone_parser::models::free_company::FreeCompany;
 
-use rocket_contrib::json::Json;
+use rocket::State;
 
 use tokio::runtime::Runtime;
 
 #[get("/free_company/<id>")]
-pub fn get(id: u64, scraper: State<LodestoneScraper>, mut redis: Redis, runtime: State<Runtime>) -> Result<Json<RouteResult<FreeCompany>>> {
-  let key = format!("free_company_{}", id);
-  cached!(runtime, redis, key => {
-    runtime.handle().block_on(scraper.free_company(id)).into()
-  })
+pub fn get(id: u64, conn: DbConn, mut pool: Redis, runtime: State<Runtime>) -> Result<RouteResult<FreeCompany>> {
+  // get free company stored in database
+  let db_fc: Option<DatabaseFreeCompany> = free_companies::table
+    .find(U64(id))
+    .get_result(&*conn)
+    .optional()?;
+  // deserialise and update frecency if free company was in database
+  if let Some(dbfc) = db_fc {
+    let fc: FreeCompany = serde_json::from_value(dbfc.data)?;
+    let new_frecency = crate::frecency::frecency(Some(dbfc.frecency));
+    diesel::update(free_companies::table)
+      .set(free_companies::frecency.eq(new_frecency))
+      .filter(free_companies::id.eq(dbfc.id))
+      .execute(&*conn)?;
+    return Ok(RouteResult::Success {
+      result: fc,
+      last_update: Utc.from_utc_datetime(&dbfc.last_update),
+    });
+  }
+  // otherwise check for a negative result or queue the free company
+  crate::routes::queued(&runtime, &mut pool, "free_company", id, Priority::Interactive)
 }
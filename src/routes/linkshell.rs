@@ -1,56 +1,78 @@
 use crate::{
+  cache::{CacheKey, CacheKind},
   cached,
   error::*,
+  database::{
+    DbConn,
+    models::{U64, linkshells::DatabaseLinkshell},
+    schema::linkshells,
+  },
   redis::Redis,
   routes::RouteResult,
+  scraper::Scraper,
+  workers::queue::Priority,
 };
 
-use lodestone_parser::models::linkshell::Linkshell;
+use chrono::{TimeZone, Utc};
 
-use lodestone_scraper::LodestoneScraper;
+use diesel::prelude::*;
 
-use rocket::{State, request::Form};
+use lodestone_parser::models::linkshell::Linkshell;
 
-use rocket_contrib::json::Json;
+use rocket::{State, request::Form};
 
 use tokio::runtime::Runtime;
 
-use std::{
-  collections::hash_map::DefaultHasher,
-  hash::{Hash, Hasher},
-};
-
+/// Gets a linkshell with the members of all of its pages.
 #[get("/linkshell/<id>")]
-pub fn get(id: u64, scraper: State<LodestoneScraper>, redis: Redis, runtime: State<Runtime>) -> Result<Json<RouteResult<Linkshell>>> {
-  _get(id, LinkshellData { page: 1 }, scraper, redis, runtime)
+pub fn get(id: u64, conn: DbConn, mut pool: Redis, runtime: State<Runtime>) -> Result<RouteResult<Linkshell>> {
+  // get linkshell stored in database
+  let db_ls: Option<DatabaseLinkshell> = linkshells::table
+    .find(U64(id))
+    .get_result(&*conn)
+    .optional()?;
+  // deserialise and update frecency if linkshell was in database
+  if let Some(dbl) = db_ls {
+    let ls: Linkshell = serde_json::from_value(dbl.data)?;
+    let new_frecency = crate::frecency::frecency(Some(dbl.frecency));
+    diesel::update(linkshells::table)
+      .set(linkshells::frecency.eq(new_frecency))
+      .filter(linkshells::id.eq(dbl.id))
+      .execute(&*conn)?;
+    return Ok(RouteResult::Success {
+      result: ls,
+      last_update: Utc.from_utc_datetime(&dbl.last_update),
+    });
+  }
+  // otherwise check for a negative result or queue the linkshell
+  crate::routes::queued(&runtime, &mut pool, "linkshell", id, Priority::Interactive)
 }
 
+/// Gets a single page of a linkshell's members directly from the Lodestone.
 #[get("/linkshell/<id>?<data..>")]
-pub fn get_page(id: u64, data: Form<LinkshellData>, scraper: State<LodestoneScraper>, redis: Redis, runtime: State<Runtime>) -> Result<Json<RouteResult<Linkshell>>> {
+pub fn get_page(id: u64, data: Form<LinkshellData>, scraper: State<Scraper>, redis: Redis, runtime: State<Runtime>) -> Result<RouteResult<Linkshell>> {
   _get(id, data.into_inner(), scraper, redis, runtime)
 }
 
-#[derive(Debug, FromForm, Hash)]
+#[derive(Debug, FromForm)]
 pub struct LinkshellData {
   page: u64,
 }
 
-impl LinkshellData {
-  fn as_hash(&self) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    self.hash(&mut hasher);
-    hasher.finish()
-  }
-}
-
-crate fn _get(id: u64, data: LinkshellData, scraper: State<LodestoneScraper>, mut redis: Redis, runtime: State<Runtime>) -> Result<Json<RouteResult<Linkshell>>> {
-  let key = format!("linkshell_{}_{}", id, data.as_hash());
-  cached!(runtime, redis, key => {
-    runtime.handle().block_on(
-      scraper
-        .linkshell(id)
-        .page(data.page)
-        .send()
-    ).into()
+crate fn _get(id: u64, data: LinkshellData, scraper: State<Scraper>, redis: Redis, runtime: State<Runtime>) -> Result<RouteResult<Linkshell>> {
+  let key = CacheKey::new(CacheKind::Linkshell)
+    .id(id)
+    .param("page", Some(data.page))
+    .build();
+  let scraper = (*scraper).clone();
+  cached!(runtime, redis, CacheKind::Linkshell, key => async move {
+    scraper
+      .wait()
+      .await
+      .linkshell(id)
+      .page(data.page)
+      .send()
+      .await
+      .into()
   })
 }
@@ -1,3 +1,51 @@
+use crate::cache::ParamName;
+
+use ffxiv_types::{Clan, DataCenter, Race, World};
+
+use lodestone_parser::models::GrandCompany;
+
 pub mod character;
 pub mod free_company;
 pub mod linkshell;
+
+impl ParamName for World {
+  fn param_name(&self) -> &'static str {
+    self.as_str()
+  }
+}
+
+impl ParamName for DataCenter {
+  fn param_name(&self) -> &'static str {
+    self.as_str()
+  }
+}
+
+impl ParamName for Race {
+  fn param_name(&self) -> &'static str {
+    self.as_str()
+  }
+}
+
+impl ParamName for Clan {
+  fn param_name(&self) -> &'static str {
+    self.as_str()
+  }
+}
+
+impl ParamName for GrandCompany {
+  fn param_name(&self) -> &'static str {
+    match *self {
+      GrandCompany::Flames => "flames",
+      GrandCompany::Maelstrom => "maelstrom",
+      GrandCompany::TwinAdders => "twin_adders",
+    }
+  }
+}
+
+/// Normalises a grand company for a cache key, treating ones that don't parse as missing like the
+/// search routes do.
+crate fn grand_company_param(grand_company: &Option<String>) -> Option<&'static str> {
+  grand_company.as_ref()
+    .and_then(|gc| GrandCompany::parse(gc))
+    .map(|gc| gc.param_name())
+}
@@ -1,39 +1,55 @@
 use crate::{
+  cache::{CacheKey, CacheKind, name_param, parsed_param},
   cached,
   error::*,
+  database::{
+    DbConn,
+    models::characters::DatabaseCharacter,
+    schema::characters,
+  },
   redis::Redis,
-  routes::RouteResult,
+  routes::{RouteResult, search::grand_company_param},
+  scraper::Scraper,
 };
 
-use ffxiv_types::{DataCenter, World, Race, Clan};
+use chrono::{TimeZone, Utc};
 
-use lodestone_scraper::LodestoneScraper;
+use diesel::{
+  dsl::sql,
+  pg::Pg,
+  prelude::*,
+  sql_types::{Bool, Text},
+};
+
+use ffxiv_types::{DataCenter, World, Race, Clan};
 
 use lodestone_parser::models::{
   GrandCompany,
+  character::Character,
   search::{
     Paginated,
+    Pagination,
     character::CharacterSearchItem,
   },
 };
 
-use rocket::{State, request::Form};
-
-use rocket_contrib::json::Json;
+use rocket::{State, http::Status, request::Form};
 
 use tokio::runtime::Runtime;
 
-use std::{
-  collections::hash_map::DefaultHasher,
-  hash::{Hash, Hasher},
-  str::FromStr,
-};
+use std::str::FromStr;
 
 #[get("/character/search?<data..>")]
-pub fn get(data: Form<CharacterSearchData>, scraper: State<LodestoneScraper>, mut redis: Redis, runtime: State<Runtime>) -> Result<Json<RouteResult<Paginated<CharacterSearchItem>>>> {
-  let data = data.into_inner();
-  let search_key = format!("character_search_{}", data.as_hash());
-  cached!(runtime, redis, search_key => {
+pub fn get(data: Form<CharacterSearchData>, scraper: State<Scraper>, redis: Redis, runtime: State<Runtime>) -> Result<RouteResult<Paginated<CharacterSearchItem>>> {
+  search(data.into_inner(), &scraper, redis, &runtime)
+}
+
+/// Searches the Lodestone for characters, caching the results.
+crate fn search(data: CharacterSearchData, scraper: &Scraper, redis: Redis, runtime: &Runtime) -> Result<RouteResult<Paginated<CharacterSearchItem>>> {
+  let search_key = data.cache_key();
+  let scraper = scraper.clone();
+  cached!(runtime, redis, CacheKind::CharacterSearch, search_key => async move {
+    let scraper = scraper.wait().await;
     let mut cs = scraper.character_search();
 
     if let Some(page) = data.page {
@@ -74,11 +90,11 @@ pub fn get(data: Form<CharacterSearchData>, scraper: State<LodestoneScraper>, mu
       }
     }
 
-    runtime.handle().block_on(cs.send()).into()
+    cs.send().await.into()
   })
 }
 
-#[derive(Debug, FromForm, Hash)]
+#[derive(Debug, FromForm)]
 pub struct CharacterSearchData {
   page: Option<u64>,
   name: Option<String>,
@@ -90,9 +106,160 @@ pub struct CharacterSearchData {
 }
 
 impl CharacterSearchData {
-  fn as_hash(&self) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    self.hash(&mut hasher);
-    hasher.finish()
+  /// A search for the first page of characters with a name, on a world if given.
+  crate fn by_name(name: String, world: Option<String>) -> Self {
+    CharacterSearchData {
+      page: None,
+      name: Some(name),
+      world,
+      data_center: None,
+      race: None,
+      clan: None,
+      grand_company: None,
+    }
+  }
+
+  /// The cache key of the search, which is shared by searches the Lodestone treats the same.
+  fn cache_key(&self) -> String {
+    CacheKey::new(CacheKind::CharacterSearch)
+      .param("page", Some(self.page.unwrap_or(1)))
+      .param("name", name_param(&self.name))
+      .param("world", parsed_param::<World>(&self.world))
+      .param("data_center", parsed_param::<DataCenter>(&self.data_center))
+      .param("race", parsed_param::<Race>(&self.race))
+      .param("clan", parsed_param::<Clan>(&self.clan))
+      .param("grand_company", grand_company_param(&self.grand_company))
+      .build()
+  }
+}
+
+/// The number of results in each page of a local search, matching the Lodestone.
+const LOCAL_PAGE_SIZE: i64 = 50;
+
+/// Searches the stored characters, ranking matches by frecency.
+#[get("/character/search/local?<data..>")]
+pub fn get_local(data: Form<LocalCharacterSearchData>, conn: DbConn) -> Result<RouteResult<Paginated<CharacterSearchItem>>> {
+  let data = data.into_inner();
+  let page = data.page.unwrap_or(1).max(1);
+
+  let total: i64 = data.query().count().get_result(&*conn)?;
+  let total_pages = ((total + LOCAL_PAGE_SIZE - 1) / LOCAL_PAGE_SIZE).max(1) as u64;
+  if page > total_pages {
+    return Ok(RouteResult::error_with_status(Status::BadRequest, format!("invalid page (1 through {} available)", total_pages)));
+  }
+
+  let chars: Vec<DatabaseCharacter> = data.query()
+    .order(characters::frecency.desc())
+    .offset((page as i64 - 1) * LOCAL_PAGE_SIZE)
+    .limit(LOCAL_PAGE_SIZE)
+    .load(&*conn)?;
+
+  let oldest_update = chars.iter().map(|c| c.last_update).min();
+  let results = chars
+    .into_iter()
+    .map(|c| {
+      let c: Character = serde_json::from_value(c.data)?;
+      Ok(CharacterSearchItem {
+        id: c.id,
+        name: c.name,
+        world: c.world,
+        grand_company: c.grand_company,
+        free_company_id: c.free_company_id,
+        face: c.face,
+      })
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  Ok(RouteResult::Success {
+    result: Paginated {
+      pagination: Pagination {
+        current_page: page,
+        total_pages,
+        total_results: total as u64,
+      },
+      results,
+    },
+    last_update: oldest_update
+      .map(|l| Utc.from_utc_datetime(&l))
+      .unwrap_or_else(Utc::now),
+  })
+}
+
+#[derive(Debug, FromForm)]
+pub struct LocalCharacterSearchData {
+  page: Option<u64>,
+  name: Option<String>,
+  world: Option<String>,
+  data_center: Option<String>,
+}
+
+impl LocalCharacterSearchData {
+  fn query(&self) -> characters::BoxedQuery<'static, Pg> {
+    let mut query = characters::table.into_boxed();
+
+    if let Some(ref name) = self.name {
+      // fuzzy trigram match, or a plain substring match for short names
+      query = query.filter(
+        sql::<Bool>("name % ").bind::<Text, _>(name.clone())
+          .or(characters::name.ilike(format!("%{}%", escape_like(name)))),
+      );
+    }
+
+    if let Some(world) = self.world.as_ref().and_then(|w| World::from_str(w).ok()) {
+      query = query.filter(characters::world.eq(world.as_str()));
+    }
+
+    if let Some(dc) = self.data_center.as_ref().and_then(|dc| DataCenter::from_str(dc).ok()) {
+      let worlds: Vec<&str> = dc.worlds().iter().map(|w| w.as_str()).collect();
+      query = query.filter(characters::world.eq_any(worlds));
+    }
+
+    query
+  }
+}
+
+/// Escapes the characters that are special in `LIKE` patterns, so they match literally.
+fn escape_like(s: &str) -> String {
+  let mut escaped = String::with_capacity(s.len());
+  for c in s.chars() {
+    if c == '\\' || c == '%' || c == '_' {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn data(page: Option<u64>, name: &str, world: Option<&str>, race: Option<&str>) -> CharacterSearchData {
+    CharacterSearchData {
+      page,
+      name: Some(name.to_string()),
+      world: world.map(ToString::to_string),
+      data_center: None,
+      race: race.map(ToString::to_string),
+      clan: None,
+      grand_company: None,
+    }
+  }
+
+  #[test]
+  fn equivalent_searches_share_a_key() {
+    let key = data(None, "foo bar", Some("Adamantoise"), None).cache_key();
+    // the first page is the default, names are normalised and unknown races are ignored
+    assert_eq!(key, data(Some(1), "  Foo   BAR ", Some("Adamantoise"), None).cache_key());
+    assert_eq!(key, data(None, "foo bar", Some("Adamantoise"), Some("not a race")).cache_key());
+    assert_ne!(key, data(Some(2), "foo bar", Some("Adamantoise"), None).cache_key());
+  }
+
+  #[test]
+  fn keys_are_stable() {
+    assert_eq!(
+      data(None, "foo bar", Some("Adamantoise"), None).cache_key(),
+      "cache:v1:character_search:7e9aa6e18de4eeb20506bd83dc72b5c974cc346e39eca88863bc33025761a383",
+    );
   }
 }
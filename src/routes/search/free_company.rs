@@ -1,13 +1,13 @@
 use crate::{
+  cache::{CacheKey, CacheKind, name_param, parsed_param},
   error::*,
   redis::Redis,
-  routes::RouteResult,
+  routes::{RouteResult, search::grand_company_param},
+  scraper::Scraper,
 };
 
 use ffxiv_types::{DataCenter, World};
 
-use lodestone_scraper::LodestoneScraper;
-
 use lodestone_parser::models::{
   GrandCompany,
   search::{
@@ -18,23 +18,19 @@ use lodestone_parser::models::{
 
 use rocket::{State, request::Form};
 
-use rocket_contrib::json::Json;
-
 use tokio::runtime::Runtime;
 
-use std::{
-  collections::hash_map::DefaultHasher,
-  hash::{Hash, Hasher},
-  str::FromStr,
-};
+use std::str::FromStr;
 
 use crate::cached;
 
 #[get("/free_company/search?<data..>")]
-pub fn get(data: Form<FreeCompanySearchData>, scraper: State<LodestoneScraper>, mut redis: Redis, runtime: State<Runtime>) -> Result<Json<RouteResult<Paginated<FreeCompanySearchItem>>>> {
+pub fn get(data: Form<FreeCompanySearchData>, scraper: State<Scraper>, redis: Redis, runtime: State<Runtime>) -> Result<RouteResult<Paginated<FreeCompanySearchItem>>> {
   let data = data.into_inner();
-  let key = format!("free_company_search_{}", data.as_hash());
-  cached!(runtime, redis, key => {
+  let key = data.cache_key();
+  let scraper = (*scraper).clone();
+  cached!(runtime, redis, CacheKind::FreeCompanySearch, key => async move {
+    let scraper = scraper.wait().await;
     let mut fcs = scraper.free_company_search();
 
     if let Some(page) = data.page {
@@ -63,11 +59,11 @@ pub fn get(data: Form<FreeCompanySearchData>, scraper: State<LodestoneScraper>,
       }
     }
 
-    runtime.handle().block_on(fcs.send()).into()
+    fcs.send().await.into()
   })
 }
 
-#[derive(Debug, FromForm, Hash)]
+#[derive(Debug, FromForm)]
 pub struct FreeCompanySearchData {
   page: Option<u64>,
   name: Option<String>,
@@ -77,9 +73,14 @@ pub struct FreeCompanySearchData {
 }
 
 impl FreeCompanySearchData {
-  fn as_hash(&self) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    self.hash(&mut hasher);
-    hasher.finish()
+  /// The cache key of the search, which is shared by searches the Lodestone treats the same.
+  fn cache_key(&self) -> String {
+    CacheKey::new(CacheKind::FreeCompanySearch)
+      .param("page", Some(self.page.unwrap_or(1)))
+      .param("name", name_param(&self.name))
+      .param("world", parsed_param::<World>(&self.world))
+      .param("data_center", parsed_param::<DataCenter>(&self.data_center))
+      .param("grand_company", grand_company_param(&self.grand_company))
+      .build()
   }
 }
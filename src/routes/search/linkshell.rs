@@ -1,13 +1,13 @@
 use crate::{
+  cache::{CacheKey, CacheKind, name_param, parsed_param},
   error::*,
   redis::Redis,
   routes::RouteResult,
+  scraper::Scraper,
 };
 
 use ffxiv_types::{DataCenter, World};
 
-use lodestone_scraper::LodestoneScraper;
-
 use lodestone_parser::models::search::{
   Paginated,
   linkshell::LinkshellSearchItem,
@@ -15,23 +15,19 @@ use lodestone_parser::models::search::{
 
 use rocket::{State, request::Form};
 
-use rocket_contrib::json::Json;
-
 use tokio::runtime::Runtime;
 
-use std::{
-  collections::hash_map::DefaultHasher,
-  hash::{Hash, Hasher},
-  str::FromStr,
-};
+use std::str::FromStr;
 
 use crate::cached;
 
 #[get("/linkshell/search?<data..>")]
-pub fn get<'a>(data: Form<LinkshellSearchData>, scraper: State<LodestoneScraper>, mut redis: Redis<'a>, runtime: State<Runtime>) -> Result<Json<RouteResult<Paginated<LinkshellSearchItem>>>> {
+pub fn get<'a>(data: Form<LinkshellSearchData>, scraper: State<Scraper>, redis: Redis<'a>, runtime: State<Runtime>) -> Result<RouteResult<Paginated<LinkshellSearchItem>>> {
   let data = data.into_inner();
-  let key = format!("linkshell_search_{}", data.as_hash());
-  cached!(runtime, redis, key => {
+  let key = data.cache_key();
+  let scraper = (*scraper).clone();
+  cached!(runtime, redis, CacheKind::LinkshellSearch, key => async move {
+    let scraper = scraper.wait().await;
     let mut fcs = scraper.linkshell_search();
 
     if let Some(page) = data.page {
@@ -54,11 +50,11 @@ pub fn get<'a>(data: Form<LinkshellSearchData>, scraper: State<LodestoneScraper>
       }
     }
 
-    runtime.handle().block_on(fcs.send()).into()
+    fcs.send().await.into()
   })
 }
 
-#[derive(Debug, FromForm, Hash)]
+#[derive(Debug, FromForm)]
 pub struct LinkshellSearchData {
   page: Option<u64>,
   name: Option<String>,
@@ -67,9 +63,13 @@ pub struct LinkshellSearchData {
 }
 
 impl LinkshellSearchData {
-  fn as_hash(&self) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    self.hash(&mut hasher);
-    hasher.finish()
+  /// The cache key of the search, which is shared by searches the Lodestone treats the same.
+  fn cache_key(&self) -> String {
+    CacheKey::new(CacheKind::LinkshellSearch)
+      .param("page", Some(self.page.unwrap_or(1)))
+      .param("name", name_param(&self.name))
+      .param("world", parsed_param::<World>(&self.world))
+      .param("data_center", parsed_param::<DataCenter>(&self.data_center))
+      .build()
   }
 }
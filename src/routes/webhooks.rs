@@ -0,0 +1,96 @@
+use crate::{
+  database::{
+    DbConn,
+    models::{
+      U64,
+      webhooks::{NewWebhook, NewWebhookSubscription, WebhookDelivery},
+    },
+    schema::{webhook_deliveries, webhook_subscriptions, webhooks},
+  },
+  error::*,
+  routes::admin::Admin,
+};
+
+use diesel::prelude::*;
+
+use rocket_contrib::json::Json;
+
+/// How many deliveries are returned by `deliveries`.
+const DELIVERY_LOG_SIZE: i64 = 100;
+
+/// A webhook and the resources it is subscribed to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookData {
+  /// The id of the webhook, assigned when it is registered
+  #[serde(default, skip_deserializing)]
+  pub id: i64,
+  /// The URL payloads are POSTed to
+  pub url: String,
+  /// The key payloads are signed with in the `X-Lodestone-Signature` header
+  #[serde(skip_serializing)]
+  pub secret: String,
+  /// The characters whose changes are sent to the webhook
+  #[serde(default)]
+  pub character_ids: Vec<u64>,
+  /// The free companies whose changes are sent to the webhook
+  #[serde(default)]
+  pub free_company_ids: Vec<u64>,
+}
+
+/// Registers a webhook to be sent the changes to characters and free companies when they are
+/// scraped again.
+///
+/// Only admins can register webhooks. Any URL is accepted and the deliverer POSTs to it, so open
+/// registration would let anyone make the server send requests into its own network.
+#[post("/webhooks", format = "json", data = "<data>")]
+pub fn create_webhook(_admin: Admin, data: Json<WebhookData>, conn: DbConn) -> Result<Json<WebhookData>> {
+  let mut data = data.into_inner();
+  failure::ensure!(
+    data.url.starts_with("https://") || data.url.starts_with("http://"),
+    "webhook urls must be http or https",
+  );
+  failure::ensure!(!data.secret.is_empty(), "webhooks must have a secret");
+  data.id = conn.transaction::<_, failure::Error, _>(|| {
+    let id: i64 = diesel::insert_into(webhooks::table)
+      .values(&NewWebhook {
+        url: &data.url,
+        secret: &data.secret,
+      })
+      .returning(webhooks::id)
+      .get_result(&*conn)?;
+    let characters = data.character_ids.iter().map(|&c| ("character", c));
+    let free_companies = data.free_company_ids.iter().map(|&fc| ("free_company", fc));
+    let subscriptions: Vec<NewWebhookSubscription> = characters
+      .chain(free_companies)
+      .map(|(kind, resource_id)| NewWebhookSubscription {
+        webhook_id: id,
+        kind,
+        resource_id: U64(resource_id),
+      })
+      .collect();
+    diesel::insert_into(webhook_subscriptions::table)
+      .values(&subscriptions)
+      .on_conflict_do_nothing()
+      .execute(&*conn)?;
+    Ok(id)
+  })?;
+  Ok(Json(data))
+}
+
+/// Removes a webhook and its deliveries, returning whether it existed.
+#[delete("/webhooks/<id>")]
+pub fn delete_webhook(_admin: Admin, id: i64, conn: DbConn) -> Result<Json<bool>> {
+  let deleted = diesel::delete(webhooks::table.find(id)).execute(&*conn)?;
+  Ok(Json(deleted > 0))
+}
+
+/// Lists the most recent deliveries to a webhook, newest first.
+#[get("/webhooks/<id>/deliveries")]
+pub fn deliveries(_admin: Admin, id: i64, conn: DbConn) -> Result<Json<Vec<WebhookDelivery>>> {
+  let deliveries = webhook_deliveries::table
+    .filter(webhook_deliveries::webhook_id.eq(id))
+    .order(webhook_deliveries::created.desc())
+    .limit(DELIVERY_LOG_SIZE)
+    .load(&*conn)?;
+  Ok(Json(deliveries))
+}
@@ -0,0 +1,117 @@
+use crate::{
+  config::CONFIG,
+  error::*,
+  redis::RedisPool,
+};
+
+use bb8_redis::redis::Script;
+
+use lodestone_scraper::LodestoneScraper;
+
+use std::{
+  sync::Arc,
+  time::Duration,
+};
+
+/// The Redis hash holding the rate limit's token bucket, shared by every process.
+const RATE_LIMIT_KEY: &str = "lodestone_rate_limit";
+
+/// Takes a token from the bucket in `KEYS[1]`, refilled at `ARGV[1]` tokens per second and holding
+/// at most `ARGV[2]` tokens. Returns zero if a token was taken, or how many milliseconds until one
+/// can be.
+///
+/// Redis's clock is used so that processes on different machines agree on how much has refilled.
+const RATE_LIMIT_SCRIPT: &str = r"
+redis.replicate_commands()
+local rate = tonumber(ARGV[1])
+local burst = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'last_refill')
+local tokens = tonumber(bucket[1]) or burst
+local last_refill = tonumber(bucket[2]) or now
+tokens = math.min(burst, tokens + math.max(0, now - last_refill) / 1000 * rate)
+local wait = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+else
+  wait = math.ceil((1 - tokens) / rate * 1000)
+end
+redis.call('HMSET', KEYS[1], 'tokens', tostring(tokens), 'last_refill', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(burst / rate * 1000) + 1000)
+return wait
+";
+
+/// A Lodestone scraper shared by the routes and workers, limiting the rate of requests made with it.
+///
+/// The limit is kept in Redis, so it is shared by every process scraping the Lodestone.
+#[derive(Clone)]
+pub struct Scraper {
+  scraper: Arc<LodestoneScraper>,
+  limiter: Arc<RateLimiter>,
+}
+
+impl Scraper {
+  pub fn new(redis_pool: &RedisPool) -> Self {
+    Scraper {
+      scraper: Arc::new(LodestoneScraper::default()),
+      limiter: Arc::new(RateLimiter::new(redis_pool, CONFIG.lodestone_rate, CONFIG.lodestone_burst)),
+    }
+  }
+
+  /// Waits until the rate limit allows another request, then returns the scraper to make it with.
+  ///
+  /// Every request to the Lodestone should be made through this.
+  pub async fn wait(&self) -> &LodestoneScraper {
+    self.limiter.acquire().await;
+    &self.scraper
+  }
+}
+
+/// A token bucket in Redis refilled at `rate` tokens per second, holding at most `burst` tokens.
+struct RateLimiter {
+  rate: f64,
+  burst: f64,
+  redis_pool: RedisPool,
+  script: Script,
+}
+
+impl RateLimiter {
+  fn new(redis_pool: &RedisPool, rate: f64, burst: f64) -> Self {
+    RateLimiter {
+      rate,
+      burst,
+      redis_pool: redis_pool.clone(),
+      script: Script::new(RATE_LIMIT_SCRIPT),
+    }
+  }
+
+  async fn acquire(&self) {
+    loop {
+      let wait = match self.take().await {
+        Ok(0) => return,
+        Ok(ms) => Duration::from_millis(ms),
+        Err(e) => {
+          // keep scraping without redis, but no faster than the limit in this process
+          eprintln!("error taking from the rate limit: {}", e);
+          tokio::time::delay_for(Duration::from_secs_f64(1.0 / self.rate)).await;
+          return;
+        },
+      };
+      tokio::time::delay_for(wait).await;
+    }
+  }
+
+  /// Tries to take a token, returning how many milliseconds to wait before trying again if there
+  /// was none.
+  async fn take(&self) -> Result<u64> {
+    let mut redis = self.redis_pool.get().await?;
+    let wait: u64 = self.script
+      .key(RATE_LIMIT_KEY)
+      .arg(self.rate)
+      .arg(self.burst)
+      .invoke_async(&mut *redis)
+      .await?;
+    Ok(wait)
+  }
+}
@@ -1,7 +1,52 @@
+use crate::{
+  config::CONFIG,
+  database::PostgresPool,
+  redis::RedisPool,
+  scraper::Scraper,
+};
+
+use lodestone_parser::models::linkshell::Linkshell;
+
+use lodestone_scraper::error::Error;
+
 pub mod queue;
 pub mod updater;
+pub mod webhooks;
 
 pub use self::{
   queue::queue,
   updater::updater,
+  webhooks::webhooks,
 };
+
+/// Spawns the workers enabled by the `RUN_QUEUE`, `RUN_UPDATER` and `RUN_WEBHOOKS` env vars onto
+/// the current runtime.
+pub fn start(redis_pool: &RedisPool, db_pool: &PostgresPool, scraper: &Scraper) {
+  if CONFIG.run_queue {
+    queue(redis_pool, db_pool, scraper);
+  }
+  if CONFIG.run_updater {
+    updater(db_pool, scraper);
+  }
+  if CONFIG.run_webhooks {
+    webhooks(db_pool);
+  }
+}
+
+/// Scrapes every page of a linkshell, returning the first page with the members of all the
+/// following pages appended to it.
+///
+/// The pagination describes the merged roster as a single page holding every member.
+crate async fn scrape_linkshell(scraper: &Scraper, id: u64) -> Result<Linkshell, Error> {
+  let mut linkshell = scraper.wait().await.linkshell(id).send().await?;
+  let total_pages = linkshell.members.pagination.total_pages;
+  for page in 2..=total_pages {
+    let next = scraper.wait().await.linkshell(id).page(page).send().await?;
+    linkshell.members.results.extend(next.members.results);
+  }
+  let pagination = &mut linkshell.members.pagination;
+  pagination.current_page = 1;
+  pagination.total_pages = 1;
+  pagination.total_results = linkshell.members.results.len() as u64;
+  Ok(linkshell)
+}
@@ -1,15 +1,24 @@
 use crate::{
+  config::CONFIG,
   database::{
-    models::characters::NewDatabaseCharacter,
-    schema::characters,
+    models::{
+      U64,
+      character_names::NewCharacterName,
+      character_snapshots::NewCharacterSnapshot,
+      characters::{DatabaseCharacter, NewDatabaseCharacter},
+      free_companies::NewDatabaseFreeCompany,
+      linkshells::NewDatabaseLinkshell,
+    },
+    schema::{character_snapshots, characters, free_companies, linkshells},
   },
   error::*,
   routes::RouteResult,
+  scraper::Scraper,
 };
 
 use bb8::Pool as AsyncPool;
 
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 
 use diesel::{
   pg::PgConnection,
@@ -17,64 +26,744 @@ use diesel::{
   r2d2::ConnectionManager,
 };
 
-use lodestone_scraper::LodestoneScraper;
+use lodestone_parser::models::{
+  character::Character,
+  free_company::FreeCompany,
+  linkshell::Linkshell,
+};
 
-use r2d2::Pool;
+use r2d2::{Pool, PooledConnection};
 
 use bb8_redis::{
-  redis::AsyncCommands,
+  redis::{self, AsyncCommands, aio::{Connection, PubSub}},
   RedisConnectionManager,
 };
 
+use tokio::stream::StreamExt;
+
+use std::collections::HashMap;
+
+/// The kinds of resources that can be queued for scraping.
+///
+/// `character_refresh` holds stored characters that clients asked to have scraped again.
+crate const KINDS: &[&str] = &["character", "character_refresh", "free_company", "linkshell"];
+
+/// The Redis key holding the time, in milliseconds, at which a worker last took a resource from
+/// the queues.
+const LAST_POP: &str = "queue_last_pop";
+/// The Redis list of the recent gaps, in milliseconds, between pops made while the queues were
+/// busy.
+const POP_GAPS: &str = "queue_pop_gaps";
+/// How many entries of `POP_GAPS` are kept to estimate throughput from.
+const POP_GAP_SAMPLES: usize = 100;
+/// How many seconds the worker blocks waiting for an empty queue before checking for retries.
+const POP_TIMEOUT: usize = 5;
+/// The Redis sorted set of failed scrapes waiting to be retried, as `<queue>:<id>` scored by the
+/// Unix timestamp they are due at.
+crate const RETRIES: &str = "queue_retries";
+/// The Redis hash of resources that failed to scrape too many times, keyed by `<kind>_<id>`.
+crate const DEAD_LETTERS: &str = "queue_dead_letters";
+
+/// The lane of a scrape queue that a resource is waiting in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+  /// Lookups made by clients waiting on the result
+  Interactive,
+  /// Imports of many resources at once
+  Bulk,
+  /// Refreshes that nobody is waiting on
+  Background,
+}
+
+impl Priority {
+  crate const ALL: [Priority; 3] = [Priority::Interactive, Priority::Bulk, Priority::Background];
+
+  /// How many resources are taken from this lane out of every ten when all lanes are busy.
+  fn weight(self) -> usize {
+    match self {
+      Priority::Interactive => 6,
+      Priority::Bulk => 3,
+      Priority::Background => 1,
+    }
+  }
+
+  fn suffix(self) -> &'static str {
+    match self {
+      // interactive keeps the original queue name
+      Priority::Interactive => "",
+      Priority::Bulk => "_bulk",
+      Priority::Background => "_background",
+    }
+  }
+
+  /// The Redis list for this lane of the queue for the given kind of resource.
+  crate fn queue(self, kind: &str) -> String {
+    format!("{}_queue{}", kind, self.suffix())
+  }
+
+  /// Splits a Redis list name into the kind of resource and lane it holds.
+  fn parse_queue(queue: &str) -> Option<(&str, Priority)> {
+    KINDS.iter()
+      .flat_map(|kind| Priority::ALL.iter().map(move |p| (*kind, *p)))
+      .find(|(kind, p)| p.queue(kind) == queue)
+  }
+
+  /// The Redis lists to pop from, in order, when the worker would prefer this lane.
+  fn queues(self) -> Vec<String> {
+    std::iter::once(self)
+      .chain(Priority::ALL.iter().cloned().filter(|&p| p != self))
+      .flat_map(|p| KINDS.iter().map(move |kind| p.queue(kind)))
+      .collect()
+  }
+}
+
+/// A queued resource's place in its lane.
+crate struct Position {
+  /// The resource's place in its lane, counting from one for the next to be scraped, or zero if
+  /// it is being scraped now
+  crate position: u64,
+  /// Roughly how many seconds until the resource is scraped, based on recent throughput
+  crate estimated_wait: Option<u64>,
+}
+
+/// Adds a resource to the given lane of its queue, returning its position. Resources that are
+/// already queued keep their place.
+///
+/// Each lane numbers the resources pushed to it in `<queue>_seq` and counts the resources taken
+/// from it in `<queue>_done`, so positions count down as the lane is processed.
+crate async fn enqueue(redis: &mut Connection, kind: &str, id: u64, priority: Priority) -> Result<Position> {
+  let entries = claim(redis, kind, &priority.queue(kind), &[id]).await?;
+  Lanes::load(redis).await?.position(redis, &entries[0]).await
+}
+
+/// Pushes each of `ARGV[2..]` that isn't in the queue hash `KEYS[1]` onto the lane `KEYS[2]`,
+/// numbering it from `KEYS[3]` and recording `<ARGV[1]>:<seq>` in the hash. Returns the hash entry
+/// of every resource.
+const CLAIM_SCRIPT: &str = r"
+local entries = {}
+for i = 2, #ARGV do
+  local entry = redis.call('HGET', KEYS[1], ARGV[i])
+  if not entry then
+    entry = ARGV[1] .. ':' .. redis.call('INCR', KEYS[3])
+    redis.call('HSET', KEYS[1], ARGV[i], entry)
+    redis.call('RPUSH', KEYS[2], ARGV[i])
+  end
+  entries[#entries + 1] = entry
+end
+return entries
+";
+
+/// Claims a place at the end of a queue for each resource that isn't queued already and pushes it
+/// there, returning the queue hash entry of every resource.
+///
+/// The check, numbering and push happen in one script, so concurrent lookups can't queue the same
+/// resource twice, and no number or hash entry is ever left without its place in the lane.
+async fn claim(redis: &mut Connection, kind: &str, queue: &str, ids: &[u64]) -> Result<Vec<String>> {
+  let entries = redis::Script::new(CLAIM_SCRIPT)
+    .key(format!("{}_queue_hash", kind))
+    .key(queue)
+    .key(format!("{}_seq", queue))
+    .arg(queue)
+    .arg(ids)
+    .invoke_async(redis)
+    .await?;
+  Ok(entries)
+}
+
+/// Removes every copy of `ARGV[1]` from the lane `KEYS[1]` and counts them as done in `KEYS[2]`, so
+/// the positions of the resources behind them still count down to zero.
+const REMOVE_SCRIPT: &str = r"
+local removed = redis.call('LREM', KEYS[1], 0, ARGV[1])
+if removed > 0 then
+  redis.call('INCRBY', KEYS[2], removed)
+end
+return removed
+";
+
+/// Takes a resource out of every lane of its queue and out of the retries.
+crate async fn remove(redis: &mut Connection, kind: &str, id: u64) -> Result<()> {
+  let script = redis::Script::new(REMOVE_SCRIPT);
+  for &priority in &Priority::ALL {
+    let queue = priority.queue(kind);
+    script
+      .key(&queue)
+      .key(format!("{}_done", queue))
+      .arg(id)
+      .invoke_async::<_, u64>(redis)
+      .await?;
+    redis.zrem::<_, _, ()>(RETRIES, format!("{}:{}", queue, id)).await?;
+  }
+  redis::pipe()
+    .hdel(format!("{}_queue_hash", kind), id).ignore()
+    .hdel(format!("{}_queue_attempts", kind), id).ignore()
+    .query_async::<_, ()>(redis)
+    .await?;
+  Ok(())
+}
+
+/// Empties every lane of the queue for a kind of resource, along with its retries.
+crate async fn clear(redis: &mut Connection, kind: &str) -> Result<()> {
+  let retries: Vec<String> = redis.zrange(RETRIES, 0, -1).await?;
+  for &priority in &Priority::ALL {
+    let queue = priority.queue(kind);
+    let seq: Option<u64> = redis.get(format!("{}_seq", queue)).await?;
+    let mut pipe = redis::pipe();
+    // mark everything pushed so far as done so the positions of new entries start from one
+    pipe
+      .atomic()
+      .del(&queue).ignore()
+      .set(format!("{}_done", queue), seq.unwrap_or(0)).ignore();
+    // retries would otherwise be pushed back onto the lane once they're due
+    for entry in &retries {
+      if entry.rfind(':').map(|i| &entry[..i]) == Some(queue.as_str()) {
+        pipe.zrem(RETRIES, entry).ignore();
+      }
+    }
+    pipe.query_async::<_, ()>(redis).await?;
+  }
+  redis::pipe()
+    .del(format!("{}_queue_hash", kind)).ignore()
+    .del(format!("{}_queue_attempts", kind)).ignore()
+    .query_async::<_, ()>(redis)
+    .await?;
+  Ok(())
+}
+
+/// Adds many resources to the given lane of their queue at once, returning their positions.
+/// Resources that are already queued keep their place.
+crate async fn enqueue_many(redis: &mut Connection, kind: &str, ids: &[u64], priority: Priority) -> Result<HashMap<u64, Position>> {
+  if ids.is_empty() {
+    return Ok(HashMap::new());
+  }
+  let entries = claim(redis, kind, &priority.queue(kind), ids).await?;
+  let mut lanes = Lanes::load(redis).await?;
+  let mut positions = HashMap::with_capacity(ids.len());
+  for (&id, entry) in ids.iter().zip(&entries) {
+    positions.insert(id, lanes.position(redis, entry).await?);
+  }
+  Ok(positions)
+}
+
+/// Finds the position of a resource in its queue, if it is queued.
+crate async fn position(redis: &mut Connection, kind: &str, id: u64) -> Result<Option<Position>> {
+  let entry: Option<String> = redis.hget(format!("{}_queue_hash", kind), id).await?;
+  match entry {
+    Some(entry) => Ok(Some(Lanes::load(redis).await?.position(redis, &entry).await?)),
+    None => Ok(None),
+  }
+}
+
+/// The prefix of queue hash entries for resources waiting out their backoff before a retry.
+const RETRY_PREFIX: &str = "retry:";
+
+/// Where a queue hash entry says a resource is.
+enum Entry<'a> {
+  /// Waiting in a lane, numbered as in `<queue>_seq`
+  Queued(&'a str, u64),
+  /// Waiting to be pushed back onto a lane at the given Unix timestamp
+  Retrying(&'a str, i64),
+  /// Waiting since before lanes were numbered, at the position it was queued at
+  Unnumbered(u64),
+}
+
+/// Parses a queue hash entry of the form `<queue>:<seq>`, or `retry:<queue>:<due>` while a failed
+/// scrape waits to be retried.
+///
+/// Entries from before lanes were numbered only hold the position at the time of queueing.
+fn parse_entry(entry: &str) -> Result<Entry> {
+  let i = match entry.rfind(':') {
+    Some(i) => i,
+    None => return Ok(Entry::Unnumbered(entry.parse()?)),
+  };
+  let (queue, n) = (&entry[..i], &entry[i + 1..]);
+  if queue.starts_with(RETRY_PREFIX) {
+    return Ok(Entry::Retrying(&queue[RETRY_PREFIX.len()..], n.parse()?));
+  }
+  Ok(Entry::Queued(queue, n.parse()?))
+}
+
+/// What is needed to turn queue hash entries into positions, fetched at most once for each lane.
+struct Lanes {
+  ms_per_pop: Option<f64>,
+  done: HashMap<String, u64>,
+  shares: HashMap<String, f64>,
+}
+
+impl Lanes {
+  async fn load(redis: &mut Connection) -> Result<Self> {
+    Ok(Lanes {
+      ms_per_pop: ms_per_pop(redis).await?,
+      done: HashMap::new(),
+      shares: HashMap::new(),
+    })
+  }
+
+  async fn position(&mut self, redis: &mut Connection, entry: &str) -> Result<Position> {
+    let (queue, position, backoff) = match parse_entry(entry)? {
+      Entry::Queued(queue, seq) => (queue, seq.saturating_sub(self.done(redis, queue).await?), None),
+      Entry::Retrying(queue, due) => {
+        // the resource goes to the back of its lane once its backoff has passed
+        let waiting: u64 = redis.llen(queue).await?;
+        (queue, waiting + 1, Some((due - Utc::now().timestamp()).max(0) as u64))
+      },
+      Entry::Unnumbered(position) => return Ok(Position {
+        position,
+        estimated_wait: None,
+      }),
+    };
+    let share = self.share(redis, queue).await?;
+    let estimated_wait = match backoff {
+      Some(backoff) => Some(backoff + estimate(self.ms_per_pop, share, position).unwrap_or(0)),
+      None => estimate(self.ms_per_pop, share, position),
+    };
+    Ok(Position {
+      position,
+      estimated_wait,
+    })
+  }
+
+  async fn done(&mut self, redis: &mut Connection, queue: &str) -> Result<u64> {
+    if let Some(&done) = self.done.get(queue) {
+      return Ok(done);
+    }
+    let done: Option<u64> = redis.get(format!("{}_done", queue)).await?;
+    self.done.insert(queue.to_string(), done.unwrap_or(0));
+    Ok(done.unwrap_or(0))
+  }
+
+  async fn share(&mut self, redis: &mut Connection, queue: &str) -> Result<f64> {
+    if let Some(&share) = self.shares.get(queue) {
+      return Ok(share);
+    }
+    let share = match Priority::parse_queue(queue) {
+      Some((_, priority)) => lane_share(redis, priority).await?,
+      None => 1.0,
+    };
+    self.shares.insert(queue.to_string(), share);
+    Ok(share)
+  }
+}
+
+/// Finds the average number of milliseconds between the workers' recent pops while the queues were
+/// busy.
+async fn ms_per_pop(redis: &mut Connection) -> Result<Option<f64>> {
+  let gaps: Vec<u64> = redis.lrange(POP_GAPS, 0, -1).await?;
+  if gaps.is_empty() {
+    return Ok(None);
+  }
+  Ok(Some(gaps.iter().sum::<u64>() as f64 / gaps.len() as f64))
+}
+
+/// Records the time of a pop in `KEYS[1]` and, if `ARGV[1]` is `1`, the gap since the previous pop
+/// in the list `KEYS[2]`, keeping `ARGV[2]` gaps.
+///
+/// Redis's clock is used so that workers on different machines agree on the gaps.
+const RECORD_POP_SCRIPT: &str = r"
+redis.replicate_commands()
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local last = tonumber(redis.call('GETSET', KEYS[1], now))
+if ARGV[1] == '1' and last and now >= last then
+  redis.call('LPUSH', KEYS[2], now - last)
+  redis.call('LTRIM', KEYS[2], 0, tonumber(ARGV[2]) - 1)
+end
+";
+
+/// Records a pop for the throughput estimate.
+///
+/// Only pops that found a resource already waiting count towards the estimate. The gap before a
+/// pop that had to block includes time spent waiting on empty queues, however long a scrape takes.
+async fn record_pop(redis: &mut Connection, busy: bool) -> Result<()> {
+  redis::Script::new(RECORD_POP_SCRIPT)
+    .key(LAST_POP)
+    .key(POP_GAPS)
+    .arg(if busy { 1 } else { 0 })
+    .arg(POP_GAP_SAMPLES)
+    .invoke_async::<_, ()>(redis)
+    .await?;
+  Ok(())
+}
+
+/// Pops from the first of `KEYS` that isn't empty without blocking, returning the list and the
+/// resource popped.
+const POP_SCRIPT: &str = r"
+for i = 1, #KEYS do
+  local id = redis.call('LPOP', KEYS[i])
+  if id then
+    return {KEYS[i], id}
+  end
+end
+return false
+";
+
+/// Finds the fraction of pops that go to a lane, given which lanes have resources waiting in them.
+///
+/// Lanes are served in proportion to their weights, but only among lanes that aren't empty.
+async fn lane_share(redis: &mut Connection, priority: Priority) -> Result<f64> {
+  let mut pipe = redis::pipe();
+  for p in Priority::ALL.iter() {
+    for kind in KINDS {
+      pipe.llen(p.queue(kind));
+    }
+  }
+  let lens: Vec<u64> = pipe.query_async(redis).await?;
+  let busy: usize = Priority::ALL.iter()
+    .zip(lens.chunks(KINDS.len()))
+    .filter(|&(&p, lens)| p == priority || lens.iter().any(|&len| len > 0))
+    .map(|(p, _)| p.weight())
+    .sum();
+  Ok(priority.weight() as f64 / busy as f64)
+}
+
+fn estimate(ms_per_pop: Option<f64>, share: f64, position: u64) -> Option<u64> {
+  ms_per_pop.map(|ms| (ms * position as f64 / share / 1000.0).ceil() as u64)
+}
+
+/// How a queued scrape finished, published when the resource leaves the queue.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+crate enum Completion {
+  /// The resource was scraped and stored.
+  Success,
+  /// The resource doesn't exist.
+  NotFound,
+  /// The resource was moved to the dead-letter queue.
+  Error {
+    /// The error from the last attempt
+    error: String,
+  },
+}
+
+/// The Redis pub/sub channel that completions of queued scrapes of a resource are published to.
+crate fn completion_channel(kind: &str, id: u64) -> String {
+  format!("{}_completed_{}", kind, id)
+}
+
+/// Subscribes to the completions of queued scrapes of a resource of each of the given kinds.
+crate async fn subscribe(kinds: &[&str], id: u64) -> Result<PubSub> {
+  let mut pubsub = crate::redis::pubsub().await?;
+  for kind in kinds {
+    pubsub.subscribe(completion_channel(kind, id)).await?;
+  }
+  Ok(pubsub)
+}
+
+/// Waits up to `timeout` for a scrape that `pubsub` is subscribed to to complete.
+crate async fn wait_for_completion(pubsub: &mut PubSub, timeout: std::time::Duration) -> Result<Option<Completion>> {
+  let msg = match tokio::time::timeout(timeout, pubsub.on_message().next()).await {
+    Ok(Some(msg)) => msg,
+    _ => return Ok(None),
+  };
+  let payload: String = msg.get_payload()?;
+  Ok(Some(serde_json::from_str(&payload)?))
+}
+
+/// A resource that could not be scraped after the maximum number of attempts.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+  pub kind: String,
+  pub id: u64,
+  pub attempts: u32,
+  /// The error from the last attempt
+  pub error: String,
+  /// When the last attempt failed
+  pub failed_at: DateTime<Utc>,
+}
+
+/// Schedules a failed scrape to be retried with exponential backoff, or moves it to the
+/// dead-letter queue if it has been attempted too many times.
+async fn retry_or_bury(redis: &mut Connection, kind: &str, queue: &str, id: u64, error: &failure::Error) -> Result<()> {
+  let attempts_key = format!("{}_queue_attempts", kind);
+  let attempts: u32 = redis.hincr(&attempts_key, id, 1).await?;
+  if attempts < CONFIG.max_scrape_attempts {
+    let delay = CONFIG.scrape_retry_delay.saturating_mul(1 << (attempts - 1).min(20));
+    let due = Utc::now().timestamp().saturating_add(delay as i64);
+    redis::pipe()
+      .atomic()
+      .zadd(RETRIES, format!("{}:{}", queue, id), due).ignore()
+      // positions report the backoff until the resource is pushed back onto its lane
+      .hset(format!("{}_queue_hash", kind), id, format!("{}{}:{}", RETRY_PREFIX, queue, due)).ignore()
+      .query_async::<_, ()>(redis)
+      .await?;
+    return Ok(());
+  }
+  let dead = DeadLetter {
+    kind: kind.to_string(),
+    id,
+    attempts,
+    error: error.to_string(),
+    failed_at: Utc::now(),
+  };
+  redis::pipe()
+    .atomic()
+    .hset(DEAD_LETTERS, format!("{}_{}", kind, id), serde_json::to_string(&dead)?).ignore()
+    .hdel(&attempts_key, id).ignore()
+    .hdel(format!("{}_queue_hash", kind), id).ignore()
+    .publish(
+      completion_channel(kind, id),
+      serde_json::to_string(&Completion::Error { error: dead.error.clone() })?,
+    ).ignore()
+    .query_async::<_, ()>(redis)
+    .await?;
+  Ok(())
+}
+
+/// Removes `ARGV[1]` from the retries `KEYS[1]` and, if it was still there, pushes `ARGV[2]` back
+/// onto the lane `KEYS[3]` numbered from `KEYS[4]`, recording `<ARGV[3]>:<seq>` in the queue hash
+/// `KEYS[2]`.
+const REQUEUE_SCRIPT: &str = r"
+if redis.call('ZREM', KEYS[1], ARGV[1]) == 0 then
+  return 0
+end
+local seq = redis.call('INCR', KEYS[4])
+redis.call('HSET', KEYS[2], ARGV[2], ARGV[3] .. ':' .. seq)
+redis.call('RPUSH', KEYS[3], ARGV[2])
+return 1
+";
+
+/// Puts failed scrapes whose backoff has passed back onto their queues.
+async fn requeue_due(redis: &mut Connection) -> Result<()> {
+  let due: Vec<String> = redis.zrangebyscore(RETRIES, "-inf", Utc::now().timestamp()).await?;
+  let script = redis::Script::new(REQUEUE_SCRIPT);
+  for entry in due {
+    let parsed = entry.rfind(':')
+      .and_then(|i| Some((Priority::parse_queue(&entry[..i])?.0, &entry[..i], entry[i + 1..].parse::<u64>().ok()?)));
+    let (kind, queue, id) = match parsed {
+      Some(parsed) => parsed,
+      None => {
+        redis.zrem::<_, _, ()>(RETRIES, &entry).await?;
+        continue;
+      },
+    };
+    // the script only requeues entries it removed, in case another worker got to them first
+    script
+      .key(RETRIES)
+      .key(format!("{}_queue_hash", kind))
+      .key(queue)
+      .key(format!("{}_seq", queue))
+      .arg(&entry)
+      .arg(id)
+      .arg(queue)
+      .invoke_async::<_, u64>(redis)
+      .await?;
+  }
+  Ok(())
+}
+
+/// Spawns the configured number of tasks consuming the scrape queues.
 pub fn queue(
   redis_pool: &AsyncPool<RedisConnectionManager>,
   db_pool: &Pool<ConnectionManager<PgConnection>>,
+  scraper: &Scraper,
+) {
+  for _ in 0..CONFIG.queue_workers {
+    consumer(redis_pool, db_pool, scraper);
+  }
+}
+
+fn consumer(
+  redis_pool: &AsyncPool<RedisConnectionManager>,
+  db_pool: &Pool<ConnectionManager<PgConnection>>,
+  scraper: &Scraper,
 ) {
   let redis_pool = redis_pool.clone();
   let db_pool = db_pool.clone();
+  let scraper = scraper.clone();
   tokio::task::spawn(async move {
-    let scraper = LodestoneScraper::default();
+    async fn inner(
+      redis_pool: &AsyncPool<RedisConnectionManager>,
+      db_pool: &Pool<ConnectionManager<PgConnection>>,
+      scraper: &Scraper,
+      pop_conn: &mut Option<Connection>,
+      preferred: Priority,
+    ) -> Result<()> {
+      requeue_due(&mut *redis_pool.get().await?).await?;
 
-    async fn inner(redis_pool: &AsyncPool<RedisConnectionManager>, db_pool: &Pool<ConnectionManager<PgConnection>>, scraper: &LodestoneScraper) -> Result<()> {
-      let mut redis = redis_pool.get().await?;
-      let conn = db_pool.get()?;
-
-      let pop: Vec<String> = redis.blpop("character_queue", 0).await?;
-      let id: u64 = pop[1].parse()?;
-      let character = match scraper.character(id).await {
-        Ok(c) => c,
-        Err(lodestone_scraper::error::Error::NotFound) => {
-          redis.set_ex(
-            &format!("character_{}", id),
-            serde_json::to_string(&RouteResult::NotFound::<()>)?,
-            1800,
-          ).await?;
-          redis.hdel("character_queue_hash", id).await?;
-          return Ok(());
+      // blpop holds its connection while the queues are empty, so it gets its own instead of one
+      // the routes share. a connection that errored is dropped and replaced
+      let mut blocking = match pop_conn.take() {
+        Some(c) => c,
+        None => crate::redis::connection().await?,
+      };
+      // both pops take from the first non-empty list, so the preferred lane is served first. blpop
+      // is only used when every list is empty, so the pops that had to wait are known. it times out
+      // so that retries are requeued even when the queues are empty
+      let queues = preferred.queues();
+      let script = redis::Script::new(POP_SCRIPT);
+      let mut invocation = script.prepare_invoke();
+      for queue in &queues {
+        invocation.key(queue);
+      }
+      let popped: Option<Vec<String>> = invocation.invoke_async(&mut blocking).await?;
+      let (pop, busy) = match popped {
+        Some(p) => (p, true),
+        None => match blocking.blpop::<_, Option<Vec<String>>>(queues, POP_TIMEOUT).await? {
+          Some(p) => (p, false),
+          None => {
+            *pop_conn = Some(blocking);
+            return Ok(());
+          },
         },
+      };
+      // only check out connections once there is something to scrape
+      let checked_out: Result<_> = async {
+        let redis = redis_pool.get().await?;
+        Ok((redis, db_pool.get()?))
+      }.await;
+      let (mut redis, conn) = match checked_out {
+        Ok(c) => c,
         Err(e) => {
-          redis.hdel("character_queue_hash", id).await?;
-          return Err(e)?;
+          // put the resource back at the front of its lane
+          blocking.lpush::<_, _, ()>(&pop[0], &pop[1]).await?;
+          *pop_conn = Some(blocking);
+          return Err(e);
         },
       };
-      let ndc = NewDatabaseCharacter {
-        id: id.into(),
-        data: serde_json::to_value(&character)?,
-        frecency: crate::frecency::frecency(None),
-        last_update: Utc::now().naive_utc(),
+      *pop_conn = Some(blocking);
+      let kind = match Priority::parse_queue(&pop[0]) {
+        Some((kind, _)) => kind,
+        None => failure::bail!("unknown queue {}", pop[0]),
       };
-      diesel::insert_into(characters::table)
-        .values(&ndc)
-        .execute(&*conn)?;
-      redis.hdel("character_queue_hash", id).await?;
-
-      Ok(())
+      let id: u64 = pop[1].parse()?;
+      redis.incr::<_, _, ()>(format!("{}_done", pop[0]), 1).await?;
+      record_pop(&mut *redis, busy).await?;
+      let queue_hash = format!("{}_queue_hash", kind);
+      let attempts_key = format!("{}_queue_attempts", kind);
+      let scraped: Result<()> = match kind {
+        "character" => match scraper.wait().await.character(id).await {
+          Ok(c) => insert_character(&conn, id, &c),
+          Err(e) => Err(e.into()),
+        },
+        "character_refresh" => {
+          // a failed lookup is retried like a failed scrape, so the refresh isn't left in the queue
+          // hash without being in any lane
+          let c: Result<Option<DatabaseCharacter>> = characters::table
+            .find(U64(id))
+            .get_result(&conn)
+            .optional()
+            .map_err(Into::into);
+          match c {
+            Ok(Some(c)) => super::updater::update_character(db_pool, &c, scraper).await,
+            // the character was deleted after it was queued
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+          }
+        },
+        "free_company" => match scraper.wait().await.free_company(id).await {
+          Ok(fc) => insert_free_company(&conn, id, &fc),
+          Err(e) => Err(e.into()),
+        },
+        "linkshell" => match super::scrape_linkshell(scraper, id).await {
+          Ok(ls) => insert_linkshell(&conn, id, &ls),
+          Err(e) => Err(e.into()),
+        },
+        _ => unreachable!(),
+      };
+      let error = match scraped {
+        Ok(()) => {
+          redis::pipe()
+            .hdel(&queue_hash, id).ignore()
+            .hdel(&attempts_key, id).ignore()
+            .publish(completion_channel(kind, id), serde_json::to_string(&Completion::Success)?).ignore()
+            .query_async::<_, ()>(&mut *redis)
+            .await?;
+          return Ok(());
+        },
+        Err(e) => e,
+      };
+      if let Some(lodestone_scraper::error::Error::NotFound) = error.downcast_ref() {
+        let mut pipe = redis::pipe();
+        // refreshes are of stored characters, whose lookups don't check for negative results
+        if kind != "character_refresh" {
+          pipe.set_ex(
+            &format!("{}_{}", kind, id),
+            serde_json::to_string(&RouteResult::NotFound::<()>)?,
+            CONFIG.not_found_ttl,
+          ).ignore();
+        }
+        pipe
+          .hdel(&queue_hash, id).ignore()
+          .hdel(&attempts_key, id).ignore()
+          .publish(completion_channel(kind, id), serde_json::to_string(&Completion::NotFound)?).ignore()
+          .query_async::<_, ()>(&mut *redis)
+          .await?;
+        return Ok(());
+      }
+      retry_or_bury(&mut *redis, kind, &pop[0], id, &error).await?;
+      Err(error)
     };
-    loop {
-      if let Err(e) = inner(&redis_pool, &db_pool, &scraper).await {
+    // each lane is preferred in proportion to its weight
+    let schedule: Vec<Priority> = Priority::ALL.iter()
+      .flat_map(|&p| std::iter::repeat(p).take(p.weight()))
+      .collect();
+    let mut pop_conn = None;
+    for &preferred in schedule.iter().cycle() {
+      // the scraper's rate limit paces the queue, so only back off after errors
+      if let Err(e) = inner(&redis_pool, &db_pool, &scraper, &mut pop_conn, preferred).await {
         eprintln!("error in queue task: {}", e);
+        tokio::time::delay_for(Duration::seconds(5).to_std().unwrap()).await;
       }
-      tokio::time::delay_for(Duration::seconds(5).to_std().unwrap()).await;
     }
   });
 }
+
+fn insert_character(conn: &PooledConnection<ConnectionManager<PgConnection>>, id: u64, character: &Character) -> Result<()> {
+  let now = Utc::now().naive_utc();
+  let data = serde_json::to_value(character)?;
+  let snapshot = NewCharacterSnapshot {
+    character_id: id.into(),
+    data: data.clone(),
+    created: now,
+  };
+  let ndc = NewDatabaseCharacter {
+    id: id.into(),
+    data,
+    frecency: crate::frecency::frecency(None),
+    last_update: now,
+  };
+  conn.transaction::<_, failure::Error, _>(|| {
+    let inserted = diesel::insert_into(characters::table)
+      .values(&ndc)
+      .on_conflict_do_nothing()
+      .execute(&**conn)?;
+    // the character was already stored by an earlier scrape
+    if inserted == 0 {
+      return Ok(());
+    }
+    diesel::insert_into(character_snapshots::table)
+      .values(&snapshot)
+      .execute(&**conn)?;
+    NewCharacterName::record(&**conn, id.into(), &snapshot.data, now)?;
+    Ok(())
+  })
+}
+
+fn insert_free_company(conn: &PooledConnection<ConnectionManager<PgConnection>>, id: u64, free_company: &FreeCompany) -> Result<()> {
+  let ndfc = NewDatabaseFreeCompany {
+    id: id.into(),
+    data: serde_json::to_value(free_company)?,
+    frecency: crate::frecency::frecency(None),
+    last_update: Utc::now().naive_utc(),
+  };
+  // a free company that was already stored is left for the updater
+  diesel::insert_into(free_companies::table)
+    .values(&ndfc)
+    .on_conflict_do_nothing()
+    .execute(&**conn)?;
+  Ok(())
+}
+
+fn insert_linkshell(conn: &PooledConnection<ConnectionManager<PgConnection>>, id: u64, linkshell: &Linkshell) -> Result<()> {
+  let ndl = NewDatabaseLinkshell {
+    id: id.into(),
+    data: serde_json::to_value(linkshell)?,
+    frecency: crate::frecency::frecency(None),
+    last_update: Utc::now().naive_utc(),
+  };
+  // a linkshell that was already stored is left for the updater
+  diesel::insert_into(linkshells::table)
+    .values(&ndl)
+    .on_conflict_do_nothing()
+    .execute(&**conn)?;
+  Ok(())
+}
@@ -1,12 +1,20 @@
 use crate::{
   database::{
-    models::characters::DatabaseCharacter,
-    schema::characters,
+    models::{
+      character_names::NewCharacterName,
+      character_snapshots::NewCharacterSnapshot,
+      characters::DatabaseCharacter,
+      free_companies::DatabaseFreeCompany,
+      linkshells::DatabaseLinkshell,
+    },
+    schema::{character_snapshots, characters, free_companies, linkshells},
   },
   error::*,
+  scraper::Scraper,
+  workers::webhooks::queue_deliveries,
 };
 
-use chrono::{Duration, Utc};
+use chrono::{Duration, NaiveDateTime, TimeZone, Utc};
 
 use diesel::{
   pg::PgConnection,
@@ -14,61 +22,204 @@ use diesel::{
   r2d2::ConnectionManager,
 };
 
-use lodestone_scraper::LodestoneScraper;
+use r2d2::Pool;
 
-use r2d2::{Pool, PooledConnection};
+/// How long an updater has to refresh the rows it claims before other updaters may claim them.
+///
+/// The claims on the rows still waiting are renewed before each scrape, so this only has to cover
+/// one scrape under the rate limit.
+const CLAIM_MINUTES: i64 = 30;
 
-pub fn updater(db_pool: &Pool<ConnectionManager<PgConnection>>) {
+/// Claims up to 100 of the stale rows of a table with the highest frecency for this updater,
+/// skipping rows that are locked or claimed by updaters in other processes.
+macro_rules! claim_stale {
+  ($conn:expr, $table:ident, $model:ty, $frecency_sql:expr, $stale_before:expr) => {{
+    let now = Utc::now();
+    let claimed_until = (now + Duration::minutes(CLAIM_MINUTES)).naive_utc();
+    let now = now.naive_utc();
+    $conn.transaction::<_, failure::Error, _>(|| {
+      let rows: Vec<$model> = $table::table
+        .filter($table::last_update.lt($stale_before))
+        .filter($table::claimed_until.is_null().or($table::claimed_until.lt(now)))
+        .order((
+          diesel::dsl::sql::<diesel::sql_types::Float8>(&$frecency_sql).desc(),
+          $table::last_update.asc(),
+        ))
+        .limit(100)
+        .for_update()
+        .skip_locked()
+        .load(&*$conn)?;
+      let ids: Vec<_> = rows.iter().map(|r| r.id).collect();
+      diesel::update($table::table)
+        .set($table::claimed_until.eq(claimed_until))
+        .filter($table::id.eq_any(ids))
+        .execute(&*$conn)?;
+      Ok(rows)
+    })
+  }}
+}
+
+/// Renews the claims on rows of a table that are still waiting to be refreshed, so they aren't
+/// claimed by other updaters while earlier rows are scraped.
+macro_rules! renew_claims {
+  ($db_pool:expr, $table:ident, $rows:expr) => {{
+    let claimed_until = (Utc::now() + Duration::minutes(CLAIM_MINUTES)).naive_utc();
+    let ids: Vec<_> = $rows.iter().map(|r| r.id).collect();
+    diesel::update($table::table)
+      .set($table::claimed_until.eq(claimed_until))
+      .filter($table::id.eq_any(ids))
+      .execute(&*$db_pool.get()?)
+  }}
+}
+
+/// Rebases frecencies that are zero or have decayed so far that they would underflow.
+crate fn prevent_underflow(conn: &PgConnection) -> Result<()> {
+  let u_sql = format!("ln(0.001) + (extract(epoch from now()) * {:?})", crate::frecency::DECAY);
+  let s_sql = format!("exp(frecency - (extract(epoch from now()) * {:?}))", crate::frecency::DECAY);
+  diesel::update(characters::table)
+    .set(characters::frecency.eq(diesel::dsl::sql::<diesel::sql_types::Float8>(&u_sql)))
+    .filter(characters::frecency.eq(0.0)
+      .or(diesel::dsl::sql::<diesel::sql_types::Float8>(&s_sql).lt(0.000001)))
+    .execute(conn)?;
+  diesel::update(free_companies::table)
+    .set(free_companies::frecency.eq(diesel::dsl::sql::<diesel::sql_types::Float8>(&u_sql)))
+    .filter(free_companies::frecency.eq(0.0)
+      .or(diesel::dsl::sql::<diesel::sql_types::Float8>(&s_sql).lt(0.000001)))
+    .execute(conn)?;
+  diesel::update(linkshells::table)
+    .set(linkshells::frecency.eq(diesel::dsl::sql::<diesel::sql_types::Float8>(&u_sql)))
+    .filter(linkshells::frecency.eq(0.0)
+      .or(diesel::dsl::sql::<diesel::sql_types::Float8>(&s_sql).lt(0.000001)))
+    .execute(conn)?;
+  Ok(())
+}
+
+/// Scrapes a stored character again, recording its new state.
+crate async fn update_character(db_pool: &Pool<ConnectionManager<PgConnection>>, c: &DatabaseCharacter, scraper: &Scraper) -> Result<()> {
+  let scraped = scraper.wait().await.character(*c.id).await?;
+  let conn = db_pool.get()?;
+  let val = serde_json::to_value(&scraped)?;
+  let scraped_at = Utc::now();
+  let now = scraped_at.naive_utc();
+  conn.transaction::<_, failure::Error, _>(|| {
+    // record the new state in the character's history and notify webhooks if anything changed
+    if val != c.data {
+      diesel::insert_into(character_snapshots::table)
+        .values(&NewCharacterSnapshot {
+          character_id: c.id,
+          data: val.clone(),
+          created: now,
+        })
+        .execute(&conn)?;
+      queue_deliveries(
+        &*conn,
+        "character",
+        c.id,
+        (Utc.from_utc_datetime(&c.last_update), &c.data),
+        (scraped_at, &val),
+      )?;
+    }
+    NewCharacterName::record(&*conn, c.id, &val, now)?;
+    diesel::update(characters::table)
+      .set((
+        characters::last_update.eq(now),
+        characters::data.eq(val),
+        characters::claimed_until.eq(None::<NaiveDateTime>),
+      ))
+      .filter(characters::id.eq(c.id))
+      .execute(&conn)?;
+    Ok(())
+  })?;
+
+  Ok(())
+}
+
+pub fn updater(db_pool: &Pool<ConnectionManager<PgConnection>>, scraper: &Scraper) {
   let db_pool = db_pool.clone();
+  let scraper = scraper.clone();
 
   tokio::task::spawn(async move {
-    let scraper = LodestoneScraper::default();
+    async fn update_free_company(db_pool: &Pool<ConnectionManager<PgConnection>>, fc: &DatabaseFreeCompany, scraper: &Scraper) -> Result<()> {
+      let scraped = scraper.wait().await.free_company(*fc.id).await?;
+      let conn = db_pool.get()?;
+      let val = serde_json::to_value(&scraped)?;
+      let scraped_at = Utc::now();
+      conn.transaction::<_, failure::Error, _>(|| {
+        if val != fc.data {
+          queue_deliveries(
+            &*conn,
+            "free_company",
+            fc.id,
+            (Utc.from_utc_datetime(&fc.last_update), &fc.data),
+            (scraped_at, &val),
+          )?;
+        }
+        diesel::update(free_companies::table)
+          .set((
+            free_companies::last_update.eq(scraped_at.naive_utc()),
+            free_companies::data.eq(&val),
+            free_companies::claimed_until.eq(None::<NaiveDateTime>),
+          ))
+          .filter(free_companies::id.eq(fc.id))
+          .execute(&conn)?;
+        Ok(())
+      })?;
 
-    let prevent_underflow = |conn: &PooledConnection<ConnectionManager<PgConnection>>| -> Result<()> {
-      let u_sql = format!("ln(0.001) + (extract(epoch from now()) * {:?})", crate::frecency::DECAY);
-      let s_sql = format!("exp(frecency - (extract(epoch from now()) * {:?}))", crate::frecency::DECAY);
-      diesel::update(characters::table)
-        .set(characters::frecency.eq(diesel::dsl::sql::<diesel::sql_types::Float8>(&u_sql)))
-        .filter(characters::frecency.eq(0.0)
-          .or(diesel::dsl::sql::<diesel::sql_types::Float8>(&s_sql).lt(0.000001)))
-        .execute(&**conn)?;
       Ok(())
     };
 
-    async fn update_character(db_pool: &Pool<ConnectionManager<PgConnection>>, c: &DatabaseCharacter, scraper: &LodestoneScraper) -> Result<()> {
-      let scraped = scraper.character(*c.id).await?;
+    async fn update_linkshell(db_pool: &Pool<ConnectionManager<PgConnection>>, ls: &DatabaseLinkshell, scraper: &Scraper) -> Result<()> {
+      let scraped = super::scrape_linkshell(scraper, *ls.id).await?;
       let conn = db_pool.get()?;
       let val = serde_json::to_value(&scraped)?;
-      diesel::update(characters::table)
+      diesel::update(linkshells::table)
         .set((
-          characters::last_update.eq(Utc::now().naive_utc()),
-          characters::data.eq(val),
+          linkshells::last_update.eq(Utc::now().naive_utc()),
+          linkshells::data.eq(val),
+          linkshells::claimed_until.eq(None::<NaiveDateTime>),
         ))
-        .filter(characters::id.eq(c.id))
+        .filter(linkshells::id.eq(ls.id))
         .execute(&conn)?;
 
-      tokio::time::delay_for(Duration::seconds(1).to_std().unwrap()).await;
       Ok(())
     };
 
     let inner = async || -> Result<()> {
-      let conn = db_pool.get()?;
-      prevent_underflow(&conn)?;
+      prevent_underflow(&*db_pool.get()?)?;
       let sql = format!("exp(frecency - (extract(epoch from now()) * {:?}))", crate::frecency::DECAY);
       let twelve_hours_ago = (Utc::now() - Duration::hours(12)).naive_utc();
-      let chars: Vec<DatabaseCharacter> = characters::table
-        .filter(characters::last_update.lt(twelve_hours_ago))
-        .order((
-          diesel::dsl::sql::<diesel::sql_types::Float8>(&sql).desc(),
-          characters::last_update.asc(),
-        ))
-        .limit(100)
-        .load(&*conn)?;
-      for c in chars {
-        if let Err(e) = update_character(&db_pool, &c, &scraper).await {
+      // each table is claimed just before its rows are scraped, and the connection isn't held on to
+      // while scraping
+      let chars = {
+        let conn = db_pool.get()?;
+        claim_stale!(conn, characters, DatabaseCharacter, sql, twelve_hours_ago)?
+      };
+      for (i, c) in chars.iter().enumerate() {
+        renew_claims!(db_pool, characters, chars[i..])?;
+        if let Err(e) = update_character(&db_pool, c, &scraper).await {
           eprintln!("error updating character {}: {}", *c.id, e);
         }
       }
+      let fcs = {
+        let conn = db_pool.get()?;
+        claim_stale!(conn, free_companies, DatabaseFreeCompany, sql, twelve_hours_ago)?
+      };
+      for (i, fc) in fcs.iter().enumerate() {
+        renew_claims!(db_pool, free_companies, fcs[i..])?;
+        if let Err(e) = update_free_company(&db_pool, fc, &scraper).await {
+          eprintln!("error updating free company {}: {}", *fc.id, e);
+        }
+      }
+      let lss = {
+        let conn = db_pool.get()?;
+        claim_stale!(conn, linkshells, DatabaseLinkshell, sql, twelve_hours_ago)?
+      };
+      for (i, ls) in lss.iter().enumerate() {
+        renew_claims!(db_pool, linkshells, lss[i..])?;
+        if let Err(e) = update_linkshell(&db_pool, ls, &scraper).await {
+          eprintln!("error updating linkshell {}: {}", *ls.id, e);
+        }
+      }
       Ok(())
     };
     loop {
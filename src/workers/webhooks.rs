@@ -0,0 +1,333 @@
+use crate::{
+  config::CONFIG,
+  database::{
+    models::{
+      U64,
+      webhooks::{NewWebhookDelivery, Webhook, WebhookDelivery},
+    },
+    schema::{webhook_deliveries, webhook_subscriptions, webhooks},
+  },
+  diff::Diff,
+  error::*,
+};
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+
+use diesel::{
+  pg::PgConnection,
+  prelude::*,
+  r2d2::ConnectionManager,
+};
+
+use hmac::{Hmac, Mac};
+
+use r2d2::Pool;
+
+use serde_json::Value;
+
+use sha2::Sha256;
+
+use std::collections::HashMap;
+
+/// How many deliveries the deliverer claims at once.
+const BATCH_SIZE: i64 = 50;
+/// How many seconds a webhook has to respond before the attempt fails.
+const TIMEOUT_SECS: u64 = 10;
+/// How long the deliverer has to send the deliveries it claims before others may claim them: long
+/// enough for every delivery in a batch to time out, plus a minute to record the attempts.
+const CLAIM_SECS: i64 = BATCH_SIZE * TIMEOUT_SECS as i64 + 60;
+
+/// The body POSTed to a webhook when a resource it is subscribed to changes.
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+  /// `character` or `free_company`
+  kind: &'a str,
+  id: u64,
+  /// What changed between the last two scrapes of the resource
+  diff: Diff,
+}
+
+/// Queues a delivery of the changes to a resource to every webhook subscribed to it.
+///
+/// This should run in the transaction that stores the new data, so deliveries are only sent for
+/// changes that were stored.
+crate fn queue_deliveries(conn: &PgConnection, kind: &str, id: U64, from: (DateTime<Utc>, &Value), to: (DateTime<Utc>, &Value)) -> Result<()> {
+  let webhook_ids: Vec<i64> = webhook_subscriptions::table
+    .filter(webhook_subscriptions::kind.eq(kind))
+    .filter(webhook_subscriptions::resource_id.eq(id))
+    .select(webhook_subscriptions::webhook_id)
+    .load(conn)?;
+  if webhook_ids.is_empty() {
+    return Ok(());
+  }
+  let payload = serde_json::to_value(&Payload {
+    kind,
+    id: *id,
+    diff: Diff::new(from, to),
+  })?;
+  let now = Utc::now().naive_utc();
+  let deliveries: Vec<NewWebhookDelivery> = webhook_ids
+    .into_iter()
+    .map(|webhook_id| NewWebhookDelivery {
+      webhook_id,
+      payload: payload.clone(),
+      next_attempt: Some(now),
+    })
+    .collect();
+  diesel::insert_into(webhook_deliveries::table)
+    .values(&deliveries)
+    .execute(conn)?;
+  Ok(())
+}
+
+/// The hex-encoded HMAC-SHA256 of a payload, sent in the `X-Lodestone-Signature` header so
+/// webhooks can check that payloads came from us.
+fn sign(secret: &str, body: &[u8]) -> String {
+  let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("hmac takes keys of any length");
+  mac.input(body);
+  mac.result().code().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Sends a payload to a webhook, returning the status of the response if there was one and why
+/// the delivery failed if it did.
+async fn send(client: &reqwest::Client, webhook: &Webhook, delivery: &WebhookDelivery) -> (Option<i32>, Option<String>) {
+  let body = match serde_json::to_vec(&delivery.payload) {
+    Ok(b) => b,
+    Err(e) => return (None, Some(e.to_string())),
+  };
+  let res = client.post(&webhook.url)
+    .header("Content-Type", "application/json")
+    .header("X-Lodestone-Delivery", delivery.id.to_string())
+    .header("X-Lodestone-Signature", format!("sha256={}", sign(&webhook.secret, &body)))
+    .body(body)
+    .send()
+    .await;
+  match res {
+    Ok(r) if r.status().is_success() => (Some(i32::from(r.status().as_u16())), None),
+    Ok(r) => (Some(i32::from(r.status().as_u16())), Some(format!("unexpected status {}", r.status()))),
+    Err(e) => (None, Some(e.to_string())),
+  }
+}
+
+/// Finds when a delivery was delivered and when it should next be attempted, after its attempt
+/// number `attempts` succeeded or failed at `now`.
+fn schedule(attempts: i32, succeeded: bool, now: NaiveDateTime) -> (Option<NaiveDateTime>, Option<NaiveDateTime>) {
+  if succeeded {
+    (Some(now), None)
+  } else if attempts as u32 >= CONFIG.webhook_attempts {
+    (None, None)
+  } else {
+    let delay = CONFIG.webhook_retry_delay.saturating_mul(1 << (attempts as u32 - 1).min(20));
+    (None, Some(now + Duration::seconds(delay as i64)))
+  }
+}
+
+/// Spawns a task sending queued webhook deliveries, retrying failed deliveries with exponential
+/// backoff up to `WEBHOOK_ATTEMPTS` times.
+pub fn webhooks(db_pool: &Pool<ConnectionManager<PgConnection>>) {
+  let db_pool = db_pool.clone();
+
+  tokio::task::spawn(async move {
+    let client = reqwest::Client::builder()
+      .timeout(std::time::Duration::from_secs(TIMEOUT_SECS))
+      .build()
+      .expect("could not create webhook client");
+
+    let inner = async || -> Result<()> {
+      let conn = db_pool.get()?;
+      let now = Utc::now().naive_utc();
+      let claimed_until = now + Duration::seconds(CLAIM_SECS);
+      // claim due deliveries by pushing their next attempt back, so that they are tried again if
+      // this worker dies while sending them
+      let due: Vec<(WebhookDelivery, Webhook)> = conn.transaction::<_, failure::Error, _>(|| {
+        // only the deliveries are locked. joining the webhooks here would lock their rows too, and
+        // other deliverers would skip every delivery to the same webhook
+        let due: Vec<WebhookDelivery> = webhook_deliveries::table
+          .filter(webhook_deliveries::next_attempt.le(now))
+          .order(webhook_deliveries::next_attempt.asc())
+          .limit(BATCH_SIZE)
+          .for_update()
+          .skip_locked()
+          .load(&*conn)?;
+        let ids: Vec<i64> = due.iter().map(|d| d.id).collect();
+        diesel::update(webhook_deliveries::table)
+          .set(webhook_deliveries::next_attempt.eq(claimed_until))
+          .filter(webhook_deliveries::id.eq_any(ids))
+          .execute(&*conn)?;
+        let hooks: HashMap<i64, Webhook> = webhooks::table
+          .filter(webhooks::id.eq_any(due.iter().map(|d| d.webhook_id).collect::<Vec<_>>()))
+          .load::<Webhook>(&*conn)?
+          .into_iter()
+          .map(|w| (w.id, w))
+          .collect();
+        // a webhook's deliveries are deleted along with it, so every webhook is found
+        Ok(due.into_iter()
+          .filter_map(|d| hooks.get(&d.webhook_id).cloned().map(|w| (d, w)))
+          .collect())
+      })?;
+      // don't hold on to the connection while sending
+      drop(conn);
+      for (delivery, webhook) in due {
+        let (status, error) = send(&client, &webhook, &delivery).await;
+        let attempts = delivery.attempts + 1;
+        let (delivered, next_attempt) = schedule(attempts, error.is_none(), Utc::now().naive_utc());
+        diesel::update(webhook_deliveries::table)
+          .set((
+            webhook_deliveries::attempts.eq(attempts),
+            webhook_deliveries::last_status.eq(status),
+            webhook_deliveries::last_error.eq(error),
+            webhook_deliveries::delivered.eq(delivered),
+            webhook_deliveries::next_attempt.eq(next_attempt),
+          ))
+          .filter(webhook_deliveries::id.eq(delivery.id))
+          .execute(&*db_pool.get()?)?;
+      }
+      Ok(())
+    };
+    loop {
+      if let Err(e) = inner().await {
+        eprintln!("error in webhook task: {}", e);
+      }
+      tokio::time::delay_for(Duration::seconds(10).to_std().unwrap()).await;
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver},
+    thread,
+  };
+
+  /// Starts a stand-in webhook answering each request with the next of `statuses`, returning its URL
+  /// and the requests it receives.
+  fn receiver(statuses: Vec<u16>) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+      for status in statuses {
+        let (mut stream, _) = listener.accept().unwrap();
+        tx.send(read_request(&mut stream)).unwrap();
+        write!(stream, "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+      }
+    });
+    (url, rx)
+  }
+
+  /// Reads a request's head and its body of `Content-Length` bytes.
+  fn read_request(stream: &mut TcpStream) -> String {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    loop {
+      let n = stream.read(&mut chunk).unwrap();
+      if n == 0 {
+        break;
+      }
+      buf.extend_from_slice(&chunk[..n]);
+      let text = String::from_utf8_lossy(&buf).to_lowercase();
+      if let Some(end) = text.find("\r\n\r\n") {
+        let length = text[..end]
+          .lines()
+          .find_map(|line| line.strip_prefix("content-length:"))
+          .map(|len| len.trim().parse::<usize>().unwrap())
+          .unwrap_or(0);
+        if buf.len() >= end + 4 + length {
+          break;
+        }
+      }
+    }
+    String::from_utf8(buf).unwrap()
+  }
+
+  fn delivery(url: String) -> (Webhook, WebhookDelivery) {
+    let now = Utc::now().naive_utc();
+    let webhook = Webhook {
+      id: 1,
+      url,
+      secret: "secret".to_string(),
+      created: now,
+    };
+    let delivery = WebhookDelivery {
+      id: 2,
+      webhook_id: 1,
+      payload: serde_json::json!({ "kind": "character", "id": 3 }),
+      attempts: 0,
+      next_attempt: Some(now),
+      last_status: None,
+      last_error: None,
+      delivered: None,
+      created: now,
+    };
+    (webhook, delivery)
+  }
+
+  #[test]
+  fn sign_matches_rfc_4231() {
+    assert_eq!(
+      sign("Jefe", b"what do ya want for nothing?"),
+      "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+    );
+  }
+
+  #[tokio::test]
+  async fn send_signs_payloads_and_reports_failures() {
+    let (url, requests) = receiver(vec![500, 204]);
+    let (webhook, delivery) = delivery(url);
+    let client = reqwest::Client::new();
+
+    let (status, error) = send(&client, &webhook, &delivery).await;
+    assert_eq!(status, Some(500));
+    assert!(error.is_some());
+
+    let (status, error) = send(&client, &webhook, &delivery).await;
+    assert_eq!(status, Some(204));
+    assert_eq!(error, None);
+
+    let body = String::from_utf8(serde_json::to_vec(&delivery.payload).unwrap()).unwrap();
+    let signature = format!("x-lodestone-signature: sha256={}", sign("secret", body.as_bytes()));
+    for request in requests.iter().take(2) {
+      assert!(request.starts_with("POST /hook "));
+      assert!(request.to_lowercase().contains(&signature));
+      assert!(request.to_lowercase().contains("x-lodestone-delivery: 2"));
+      assert!(request.ends_with(&body));
+    }
+  }
+
+  #[tokio::test]
+  async fn failed_deliveries_are_retried_with_backoff() {
+    let (url, _requests) = receiver(vec![503, 503, 200]);
+    let (webhook, mut delivery) = delivery(url);
+    let client = reqwest::Client::new();
+    let delay = CONFIG.webhook_retry_delay as i64;
+
+    let mut backoffs = Vec::new();
+    loop {
+      let (_, error) = send(&client, &webhook, &delivery).await;
+      delivery.attempts += 1;
+      let now = Utc::now().naive_utc();
+      match schedule(delivery.attempts, error.is_none(), now) {
+        (None, Some(next)) => backoffs.push(next - now),
+        (delivered, next) => {
+          assert_eq!((delivered, next), (Some(now), None));
+          break;
+        },
+      }
+    }
+    assert_eq!(delivery.attempts, 3);
+    assert_eq!(backoffs, vec![Duration::seconds(delay), Duration::seconds(delay * 2)]);
+  }
+
+  #[test]
+  fn deliveries_stop_after_the_last_attempt() {
+    let now = Utc::now().naive_utc();
+    let last = CONFIG.webhook_attempts as i32;
+    assert_eq!(schedule(last, false, now), (None, None));
+    assert_eq!(schedule(last, true, now), (Some(now), None));
+  }
+}